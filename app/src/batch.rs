@@ -0,0 +1,262 @@
+//! Sampling more often than we send. Readings are buffered along with the time
+//! at which they were taken, and then packed into a single uplink. Successive
+//! readings tend to be close to one another, so each is conveyed as its
+//! difference from the one before, in as few bytes as that difference needs.
+//!
+//! A batch is laid out as a count of samples followed by the samples, oldest
+//! first. Each sample is five variable length integers (LEB128, least
+//! significant group first):
+//!
+//! Field          | Description
+//! time           | Seconds between the sample and the one before. For the first sample, its age when sent
+//! temperature    | Zigzag encoded difference from the previous sample, in the units of `EnvironmentalPayload`
+//! pressure       | Ditto
+//! humidity       | Ditto
+//! gas resistance | Ditto
+//!
+//! The first sample's fields are differences from zero i.e. absolute values.
+//!
+//! Should we have measured our battery, the samples are followed by a trailer
+//! of its voltage in millivolts, as two big endian bytes whatever our payload
+//! format. A decoder knows it's there by bytes remaining once it has read as
+//! many samples as were counted.
+
+use heapless::Vec;
+
+use crate::{
+    battery::BatteryVoltage,
+    codec::{EncodeError, Payload},
+    EnvironmentalPayload,
+};
+
+/// The FPort on which batches are sent, distinguishing them from single readings.
+pub const BATCH_F_PORT: u8 = 2;
+
+/// The number of samples that we buffer between uplinks.
+pub const MAX_SAMPLES: usize = 32;
+
+/// The length of our battery trailer.
+pub const BATTERY_LEN: usize = 2;
+
+// Five fields of up to five bytes each.
+const MAX_SAMPLE_LEN: usize = 25;
+
+/// A reading along with the time at which it was taken, in seconds since
+/// some arbitrary epoch, such as boot.
+pub struct Sample {
+    pub at_s: u32,
+    pub reading: EnvironmentalPayload,
+}
+
+fn push_varint(bytes: &mut Vec<u8, MAX_SAMPLE_LEN>, mut value: u64) {
+    loop {
+        let group = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            let _ = bytes.push(group);
+            break;
+        }
+        let _ = bytes.push(group | 0x80);
+    }
+}
+
+fn push_delta(bytes: &mut Vec<u8, MAX_SAMPLE_LEN>, value: i64, previous: i64) {
+    let delta = value - previous;
+    push_varint(bytes, ((delta << 1) ^ (delta >> 63)) as u64);
+}
+
+fn encode_sample(
+    sample: &Sample,
+    previous: Option<&Sample>,
+    now_s: u32,
+) -> Vec<u8, MAX_SAMPLE_LEN> {
+    let mut bytes = Vec::new();
+    let zero = EnvironmentalPayload {
+        temperature: 0,
+        pressure: 0,
+        humidity: 0,
+        gas_resistance: 0,
+    };
+    let (since_s, before) = match previous {
+        Some(previous) => (sample.at_s.wrapping_sub(previous.at_s), &previous.reading),
+        None => (now_s.wrapping_sub(sample.at_s), &zero),
+    };
+    let reading = &sample.reading;
+    push_varint(&mut bytes, u64::from(since_s));
+    push_delta(
+        &mut bytes,
+        i64::from(reading.temperature),
+        i64::from(before.temperature),
+    );
+    push_delta(
+        &mut bytes,
+        i64::from(reading.pressure),
+        i64::from(before.pressure),
+    );
+    push_delta(
+        &mut bytes,
+        i64::from(reading.humidity),
+        i64::from(before.humidity),
+    );
+    push_delta(
+        &mut bytes,
+        i64::from(reading.gas_resistance),
+        i64::from(before.gas_resistance),
+    );
+    bytes
+}
+
+/// Pack as many of the samples as fit within `max_len` bytes into the payload,
+/// oldest first, returning the number packed. Nothing is written if none fit.
+///
+/// ```
+/// use app::batch::{encode, Sample};
+/// use app::codec::Payload;
+/// use app::EnvironmentalPayload;
+///
+/// let samples = [
+///     Sample {
+///         at_s: 100,
+///         reading: EnvironmentalPayload { temperature: 2100, pressure: 101325, humidity: 48000, gas_resistance: 5000 },
+///     },
+///     Sample {
+///         at_s: 400,
+///         reading: EnvironmentalPayload { temperature: 2098, pressure: 101330, humidity: 48000, gas_resistance: 5100 },
+///     },
+/// ];
+///
+/// let mut payload = Payload::new();
+/// assert_eq!(encode(&samples, 700, 51, &mut payload), 2);
+/// assert_eq!(
+///     &payload[..],
+///     &[
+///         0x02, // Samples
+///         0xd8, 0x04, 0xe8, 0x20, 0x9a, 0xaf, 0x0c, 0x80, 0xee, 0x05, 0x90, 0x4e, // 600s old
+///         0xac, 0x02, 0x03, 0x0a, 0x00, 0xc8, 0x01, // 300s later
+///     ]
+/// );
+///
+/// // Only the first fits
+/// let mut payload = Payload::new();
+/// assert_eq!(encode(&samples, 700, 13, &mut payload), 1);
+/// assert_eq!(payload.len(), 13);
+///
+/// // Nothing fits
+/// let mut payload = Payload::new();
+/// assert_eq!(encode(&samples, 700, 12, &mut payload), 0);
+/// assert!(payload.is_empty());
+/// ```
+pub fn encode(samples: &[Sample], now_s: u32, max_len: usize, payload: &mut Payload) -> usize {
+    let max_len = max_len.min(payload.capacity());
+    let start = payload.len();
+    if start >= max_len || samples.is_empty() {
+        return 0;
+    }
+    let _ = payload.push(0);
+    let mut count = 0;
+    let mut previous = None;
+    for sample in samples.iter().take(u8::MAX as usize) {
+        let bytes = encode_sample(sample, previous, now_s);
+        if payload.len() + bytes.len() > max_len {
+            break;
+        }
+        let _ = payload.extend_from_slice(&bytes);
+        count += 1;
+        previous = Some(sample);
+    }
+    if count == 0 {
+        payload.truncate(start);
+    } else {
+        payload[start] = count as u8;
+    }
+    count
+}
+
+/// Follow a batch with our battery voltage.
+///
+/// ```
+/// use app::batch::{encode_battery, BATTERY_LEN};
+/// use app::battery::BatteryVoltage;
+/// use app::codec::Payload;
+///
+/// let mut payload = Payload::new();
+/// payload.extend_from_slice(&[0x01, 0x1f, 0x00, 0x00, 0x00, 0x00]).unwrap();
+/// encode_battery(&BatteryVoltage { mv: 3712 }, &mut payload).unwrap();
+/// assert_eq!(&payload[6..], &[0x0e, 0x80]);
+/// assert_eq!(payload.len(), 6 + BATTERY_LEN);
+/// ```
+pub fn encode_battery(battery: &BatteryVoltage, payload: &mut Payload) -> Result<(), EncodeError> {
+    payload
+        .extend_from_slice(&battery.mv.to_be_bytes())
+        .map_err(|_| EncodeError::Overflow)
+}
+
+/// Samples awaiting an uplink. Once full, the oldest sample is dropped to make
+/// room for a new one.
+///
+/// ```
+/// use app::batch::{Samples, MAX_SAMPLES};
+/// use app::codec::Payload;
+/// use app::EnvironmentalPayload;
+///
+/// let mut samples = Samples::new();
+/// for at_s in 0..MAX_SAMPLES as u32 + 1 {
+///     samples.push(at_s, EnvironmentalPayload { temperature: 0, pressure: 0, humidity: 0, gas_resistance: 0 });
+/// }
+/// assert!(samples.is_full());
+///
+/// let mut payload = Payload::new();
+/// assert_eq!(samples.take(MAX_SAMPLES as u32, 7, &mut payload), 1);
+/// assert_eq!(&payload[..], &[0x01, 0x1f, 0x00, 0x00, 0x00, 0x00]); // The oldest went
+/// assert_eq!(samples.len(), MAX_SAMPLES - 1);
+/// ```
+pub struct Samples {
+    samples: Vec<Sample, MAX_SAMPLES>,
+}
+
+impl Samples {
+    pub fn new() -> Self {
+        Samples {
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, at_s: u32, reading: EnvironmentalPayload) {
+        if self.samples.is_full() {
+            self.drop_oldest(1);
+        }
+        let _ = self.samples.push(Sample { at_s, reading });
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.samples.is_full()
+    }
+
+    /// Pack the oldest samples into a payload as per `encode`, removing those
+    /// packed and returning how many there were.
+    pub fn take(&mut self, now_s: u32, max_len: usize, payload: &mut Payload) -> usize {
+        let count = encode(&self.samples, now_s, max_len, payload);
+        self.drop_oldest(count);
+        count
+    }
+
+    fn drop_oldest(&mut self, count: usize) {
+        let len = self.samples.len();
+        self.samples.rotate_left(count);
+        self.samples.truncate(len - count);
+    }
+}
+
+impl Default for Samples {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,97 @@
+//! Our LiPo battery. Its voltage is measured each time we wake, conveyed in
+//! our uplinks, and reported to the network server as a level when it asks.
+//! Should the battery run low we send less often, and once below a cut-off we
+//! stop sending altogether so that what charge remains keeps us sampling.
+
+use serde::{Deserialize, Serialize};
+
+/// The voltage at which we regard the battery as empty, and full.
+pub const EMPTY_MV: u16 = 3300;
+pub const FULL_MV: u16 = 4200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryVoltage {
+    pub mv: u16,
+}
+
+impl BatteryVoltage {
+    /// Parse the response to `AT%XVBAT`.
+    ///
+    /// ```
+    /// use app::battery::BatteryVoltage;
+    ///
+    /// assert_eq!(BatteryVoltage::parse("%XVBAT: 3712"), Some(BatteryVoltage { mv: 3712 }));
+    /// assert_eq!(BatteryVoltage::parse("OK"), None);
+    /// ```
+    pub fn parse(line: &str) -> Option<Self> {
+        let mv = line.strip_prefix("%XVBAT:")?.trim().parse().ok()?;
+        Some(BatteryVoltage { mv })
+    }
+
+    /// The level to report in a DevStatusAns, from 1 when empty to 254 when
+    /// full.
+    ///
+    /// ```
+    /// use app::battery::BatteryVoltage;
+    ///
+    /// assert_eq!(BatteryVoltage { mv: 3000 }.level(), 1);
+    /// assert_eq!(BatteryVoltage { mv: 3750 }.level(), 127);
+    /// assert_eq!(BatteryVoltage { mv: 4300 }.level(), 254);
+    /// ```
+    pub fn level(&self) -> u8 {
+        let mv = self.mv.clamp(EMPTY_MV, FULL_MV) - EMPTY_MV;
+        (1 + u32::from(mv) * 253 / u32::from(FULL_MV - EMPTY_MV)) as u8
+    }
+}
+
+/// How we conserve a battery that is running low.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LowBatteryPolicy {
+    /// Below this, we send less often.
+    pub low_mv: u16,
+    /// Below this, we stop sending.
+    pub cutoff_mv: u16,
+    /// How many times longer we wait between sends when low.
+    pub stretch: u8,
+}
+
+impl LowBatteryPolicy {
+    /// How often to send given our battery voltage, if at all. We carry on as
+    /// normal should the voltage be unknown.
+    ///
+    /// ```
+    /// use app::battery::{BatteryVoltage, LowBatteryPolicy};
+    ///
+    /// let policy = LowBatteryPolicy { low_mv: 3600, cutoff_mv: 3400, stretch: 4 };
+    /// let send_frequency_ms = |mv| policy.send_frequency_ms(1000, mv);
+    /// assert_eq!(send_frequency_ms(Some(BatteryVoltage { mv: 3600 })), Some(1000));
+    /// assert_eq!(send_frequency_ms(Some(BatteryVoltage { mv: 3599 })), Some(4000));
+    /// assert_eq!(send_frequency_ms(Some(BatteryVoltage { mv: 3400 })), Some(4000));
+    /// assert_eq!(send_frequency_ms(Some(BatteryVoltage { mv: 3399 })), None);
+    /// assert_eq!(send_frequency_ms(None), Some(1000));
+    /// ```
+    pub fn send_frequency_ms(
+        &self,
+        send_frequency_ms: u32,
+        battery: Option<BatteryVoltage>,
+    ) -> Option<u32> {
+        match battery {
+            Some(battery) if battery.mv < self.cutoff_mv => None,
+            Some(battery) if battery.mv < self.low_mv => {
+                Some(send_frequency_ms.saturating_mul(self.stretch.max(1).into()))
+            }
+            _ => Some(send_frequency_ms),
+        }
+    }
+}
+
+impl Default for LowBatteryPolicy {
+    /// Sending a quarter as often below 3.6V, and not at all below 3.4V.
+    fn default() -> Self {
+        LowBatteryPolicy {
+            low_mv: 3600,
+            cutoff_mv: 3400,
+            stretch: 4,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Codecs lay out the application payload of our uplinks. The frame builders
+//! convey whatever bytes a codec produces, so that the layout can change, or
+//! fields be added, without touching them.
+
+use heapless::Vec;
+
+use crate::{battery::BatteryVoltage, EnvironmentalPayload};
+
+/// The maximum size of an application payload. The largest FRMPayload that
+/// any region permits with FOpts present, so that a frame always fits within
+/// `MAX_FRAME_LEN`.
+pub const MAX_PAYLOAD_LEN: usize = 222;
+
+/// An encoded application payload.
+pub type Payload = Vec<u8, MAX_PAYLOAD_LEN>;
+
+#[derive(Debug, PartialEq)]
+pub enum EncodeError {
+    /// There's no room left in the payload for the value.
+    Overflow,
+}
+
+/// Encodes values of `T` into an application payload.
+pub trait PayloadCodec<T> {
+    /// Append the encoded value to the payload. Nothing is appended if the
+    /// value doesn't fit.
+    fn encode(&self, value: &T, payload: &mut Payload) -> Result<(), EncodeError>;
+}
+
+/// Our original, fixed layout of environmental readings, all big endian:
+///
+/// Start |   End | Description
+///     0 |     1 | Temperature (C) * 100
+///     2 |     5 | Pressure (hPA) * 100
+///     6 |     9 | Humidity (%) * 1000
+///    10 |    13 | Gas Resistence
+///    14 |    15 | Battery (mV), when measured
+///
+/// ```
+/// use app::codec::{EnvironmentalCodec, Payload, PayloadCodec};
+/// use app::EnvironmentalPayload;
+///
+/// let mut payload = Payload::new();
+/// EnvironmentalCodec
+///     .encode(&EnvironmentalPayload { temperature: -2, pressure: 0, humidity: 99, gas_resistance: 1 }, &mut payload)
+///     .unwrap();
+/// assert_eq!(&payload[..], &[0xff, 0xfe, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x63, 0x00, 0x00, 0x00, 0x01]);
+///
+/// use app::battery::BatteryVoltage;
+///
+/// EnvironmentalCodec.encode(&BatteryVoltage { mv: 3712 }, &mut payload).unwrap();
+/// assert_eq!(&payload[14..], &[0x0e, 0x80]);
+/// ```
+pub struct EnvironmentalCodec;
+
+impl PayloadCodec<EnvironmentalPayload> for EnvironmentalCodec {
+    fn encode(
+        &self,
+        value: &EnvironmentalPayload,
+        payload: &mut Payload,
+    ) -> Result<(), EncodeError> {
+        payload
+            .extend_from_slice(&value.to_be_bytes())
+            .map_err(|_| EncodeError::Overflow)
+    }
+}
+
+impl PayloadCodec<BatteryVoltage> for EnvironmentalCodec {
+    fn encode(&self, value: &BatteryVoltage, payload: &mut Payload) -> Result<(), EncodeError> {
+        payload
+            .extend_from_slice(&value.mv.to_be_bytes())
+            .map_err(|_| EncodeError::Overflow)
+    }
+}
@@ -0,0 +1,466 @@
+//! Configuration is provided such that it has a stable representation
+//! for both in-memory storage per flash memory, and when communicated
+//! between devices, perhaps over serial communications. The goal is to
+//! use one form of serialisation and the method adopted uses Postcard
+//! given its generality.
+//! In particular, I wish to consider a future capability of bulk-flashing
+//! configuration to devices during their manufacturing.
+//!
+//! Our configuration is encoded as its version followed by its fields, so
+//! that devices keep their configuration when upgraded. Each version's
+//! fields remain decodable in a module of their own, along with a migration
+//! to the version that followed it. Should `Config` change, its current form
+//! is copied to such a module, the migration added, and `Version::CURRENT`
+//! moved on.
+//!
+//! Configuration is saved to the last two pages of our region of flash
+//! memory, as described by `slots`. Our first release saved it without a
+//! record header to the last page, at 0xFF000, from where it is loaded until
+//! saved again.
+
+use core::fmt;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+use postcard::{take_from_bytes, to_slice};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    battery::LowBatteryPolicy,
+    host::{Hostname, IpAddr, MAX_HOSTNAME_LEN},
+    power::{Edrx, Psm},
+    queue::DrainOrder,
+    system_mode::SystemModePolicy,
+};
+
+use slots::{Slots, HEADER_LEN};
+
+// The pages of our region holding our slots, following those of frame
+// counters and spilled uplinks. Our first release saved to the last page.
+
+const FIRST_SLOT_PAGE: u32 = 3;
+const LEGACY_PAGE: u32 = 4;
+
+pub mod slots;
+pub mod v1;
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Version {
+    V1,
+    /// Never written, and retained only so that later versions keep their
+    /// encoding.
+    Invalid,
+    V2,
+}
+
+impl Version {
+    /// The version of `Config`.
+    pub const CURRENT: Version = Version::V2;
+}
+
+// The value of erased flash memory, which no version begins with.
+
+const ERASED: u8 = 0xff;
+
+/// Why a configuration couldn't be decoded.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// A version that we don't know, as written by newer firmware.
+    UnknownVersion,
+    /// Not a configuration of the version given.
+    Corrupt,
+}
+
+/// Why what we keep in flash memory couldn't be loaded or saved.
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// What was to be saved doesn't fit the space that it is given.
+    Overflow,
+    Read,
+    Erase,
+    Write,
+    /// Of a version that we don't know, or corrupt.
+    Unrecognised,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConfigError::Overflow => "too large to save",
+            ConfigError::Read => "cannot read flash",
+            ConfigError::Erase => "cannot erase flash",
+            ConfigError::Write => "cannot write flash",
+            ConfigError::Unrecognised => "unrecognised",
+        })
+    }
+}
+
+/// The most bytes that our configuration encodes to, including its version.
+/// Postcard encodes integers with a fixed width here, and precedes each
+/// option, enum variant, and the contents of each string or vector with a
+/// byte. This must follow any change to `Config`.
+///
+/// ```
+/// use app::config::*;
+/// use app::host::{Hostname, IpAddr};
+/// use app::power::{Edrx, Psm};
+/// use app::system_mode::SystemModePolicy;
+///
+/// // Every field at its largest
+/// let mut name = Hostname::new();
+/// name.push_str(&"a".repeat(name.capacity())).unwrap();
+/// let mut psk_identity = PskIdentity::new();
+/// psk_identity.push_str(&"a".repeat(psk_identity.capacity())).unwrap();
+/// let psm = Psm { periodic_tau_s: 3600, active_time_s: 60 };
+/// let edrx = Edrx { cycle_ms: 81_920, ptw_ms: 2_560 };
+/// let config = Config {
+///     nwkskey: Some(1),
+///     appskey: Some(2),
+///     iccid: Some(3),
+///     network_server_host: Some(IpAddr::V6([0; 16])),
+///     network_server_name: Some(name),
+///     transport: Transport::Dtls,
+///     psk_identity: Some(psk_identity),
+///     psk: Some(Psk::from_slice(&[0; MAX_PSK_LEN]).unwrap()),
+///     dev_eui: Some(4),
+///     join_eui: Some(5),
+///     app_key: Some(6),
+///     system_mode: SystemModePolicy::PreferNbIot { fallback_after: 3 },
+///     psm: Some(psm),
+///     edrx: Some(edrx),
+///     watchdog_slack_ms: Some(0),
+///     resolved_host: Some(IpAddr::V6([0; 16])),
+///     ..Config::new()
+/// };
+/// let mut buf = [0; MAX_ENCODED_LEN];
+/// assert_eq!(config.encode(&mut buf).unwrap().len(), MAX_ENCODED_LEN);
+/// ```
+pub const MAX_ENCODED_LEN: usize = 1 // version
+    + 4 + 17 + 17 + 9 + 4 // net_id, session keys, iccid and send_frequency_ms
+    + (1 + 1 + 16) + (1 + 1 + MAX_HOSTNAME_LEN) + 2 // network server
+    + 1 + (1 + 1 + MAX_PSK_IDENTITY_LEN) + (1 + 1 + MAX_PSK_LEN) // transport
+    + 1 + 1 // confirmed and nb_trans
+    + 9 + 9 + 17 + 2 + 1 // joining
+    + 1 + 1 + 4 + 1 + 1 + 1 // protocol through to spill_to_flash
+    + 2 + 9 + 9 // system_mode and power saving
+    + 4 + 5 + 5 // diagnostics_frequency_ms, low_battery and watchdog_slack_ms
+    + (1 + 1 + 16); // resolved_host
+
+// Our record, with room for it to be padded to whole words of flash memory.
+
+const RECORD_LEN: usize = HEADER_LEN + MAX_ENCODED_LEN + 3;
+
+/// How LoRaWAN frames are conveyed to the network server.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Protocol {
+    /// Frames are sent as they are.
+    Raw,
+    /// Frames are wrapped in the Semtech UDP packet forwarder protocol.
+    SemtechUdp,
+}
+
+/// How datagrams are conveyed to the network server.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Transport {
+    /// Plain UDP.
+    Udp,
+    /// DTLS 1.2 with a pre-shared key, which also authenticates us to the
+    /// network server.
+    Dtls,
+}
+
+pub const MAX_PSK_IDENTITY_LEN: usize = 64;
+pub const MAX_PSK_LEN: usize = 32;
+
+pub type PskIdentity = String<MAX_PSK_IDENTITY_LEN>;
+pub type Psk = Vec<u8, MAX_PSK_LEN>;
+
+/// How sensor readings are laid out in our uplinks.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum PayloadFormat {
+    /// Our own fixed, big endian layout.
+    Environmental,
+    /// Cayenne Low Power Payload.
+    CayenneLpp,
+}
+
+/// The order in which uplinks queued while the network is unavailable are sent.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum QueueOrder {
+    OldestFirst,
+    NewestFirst,
+}
+
+impl From<QueueOrder> for DrainOrder {
+    fn from(order: QueueOrder) -> Self {
+        match order {
+            QueueOrder::OldestFirst => DrainOrder::OldestFirst,
+            QueueOrder::NewestFirst => DrainOrder::NewestFirst,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub net_id: u32,
+    pub nwkskey: Option<u128>,
+    pub appskey: Option<u128>,
+    pub iccid: Option<u64>,
+    pub send_frequency_ms: u32,
+    pub network_server_host: Option<IpAddr>,
+    pub network_server_name: Option<Hostname>,
+    pub network_server_port: u16,
+    pub transport: Transport,
+    pub psk_identity: Option<PskIdentity>,
+    pub psk: Option<Psk>,
+    pub confirmed: bool,
+    pub nb_trans: u8,
+    pub dev_eui: Option<u64>,
+    pub join_eui: Option<u64>,
+    pub app_key: Option<u128>,
+    pub dev_nonce: u16,
+    pub rejoin_after: u8,
+    pub protocol: Protocol,
+    pub payload_format: PayloadFormat,
+    pub sample_frequency_ms: u32,
+    pub max_payload_len: u8,
+    pub queue_order: QueueOrder,
+    pub spill_to_flash: bool,
+    pub system_mode: SystemModePolicy,
+    pub psm: Option<Psm>,
+    pub edrx: Option<Edrx>,
+    pub diagnostics_frequency_ms: u32,
+    pub low_battery: LowBatteryPolicy,
+    /// How long our work may take once woken before the watchdog resets us,
+    /// if at all.
+    pub watchdog_slack_ms: Option<u32>,
+    /// The address that the network server's name last resolved to, to fall
+    /// back on should we be unable to resolve it.
+    pub resolved_host: Option<IpAddr>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            net_id: 0x13_u32,
+            nwkskey: None,
+            appskey: None,
+            iccid: None,
+            send_frequency_ms: 60 * 60 * 1000, // 1 hour
+            network_server_host: None,
+            network_server_name: None,
+            network_server_port: 1694,
+            transport: Transport::Udp,
+            psk_identity: None,
+            psk: None,
+            confirmed: false,
+            nb_trans: 3,
+            dev_eui: None,
+            join_eui: None,
+            app_key: None,
+            dev_nonce: 0,
+            rejoin_after: 0,
+            protocol: Protocol::Raw,
+            payload_format: PayloadFormat::Environmental,
+            sample_frequency_ms: 0, // Sample when sending
+            max_payload_len: 51,    // The smallest maximum of any EU868 data rate
+            queue_order: QueueOrder::OldestFirst,
+            spill_to_flash: false,
+            system_mode: SystemModePolicy::NbIot,
+            psm: None,
+            edrx: None,
+            diagnostics_frequency_ms: 0, // Never
+            low_battery: LowBatteryPolicy::default(),
+            watchdog_slack_ms: Some(10 * 60 * 1000), // 10 minutes
+            resolved_host: None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (self.is_abp() || self.is_otaa())
+            && (self.network_server_host.is_some() || self.network_server_name.is_some())
+            && (self.transport == Transport::Udp || self.psk_credentials().is_some())
+    }
+
+    /// The identity and key with which a DTLS session is established.
+    pub fn psk_credentials(&self) -> Option<(&PskIdentity, &Psk)> {
+        Some((self.psk_identity.as_ref()?, self.psk.as_ref()?))
+    }
+
+    /// Activation by personalisation is used when we have session keys.
+    pub fn is_abp(&self) -> bool {
+        self.nwkskey.is_some() && self.appskey.is_some() && self.iccid.is_some()
+    }
+
+    /// Over-the-air activation is used when we have what is required to join,
+    /// and takes precedence over personalisation.
+    pub fn is_otaa(&self) -> bool {
+        self.dev_eui.is_some() && self.join_eui.is_some() && self.app_key.is_some()
+    }
+
+    /// When masquerading as a gateway, we identify ourselves with our Device EUI,
+    /// or our ICCID if we don't have one.
+    pub fn gateway_eui(&self) -> u64 {
+        self.dev_eui.or(self.iccid).unwrap_or(0)
+    }
+
+    /// We batch samples when sampling more often than we send.
+    pub fn is_batching(&self) -> bool {
+        self.sample_frequency_ms > 0 && self.sample_frequency_ms < self.send_frequency_ms
+    }
+
+    /// When batching, we wake to sample, and send every so many samples.
+    /// Otherwise we wake to send.
+    pub fn wake_frequency_ms(&self) -> u32 {
+        if self.is_batching() {
+            self.sample_frequency_ms
+        } else {
+            self.send_frequency_ms
+        }
+    }
+
+    /// Decode a configuration of any version that we know, migrating it to
+    /// the current version. Erased flash memory holds no configuration.
+    ///
+    /// ```
+    /// use app::config::{Config, DecodeError, MAX_ENCODED_LEN};
+    /// use app::host::IpAddr;
+    ///
+    /// // As written by our first release
+    /// let v1 = [
+    ///     0, 19, 0, 0, 0, 1, 16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 1, 32, 31, 30,
+    ///     29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 1, 1, 0, 0, 0, 0, 0, 68, 137, 160,
+    ///     187, 13, 0, 1, 192, 168, 0, 10, 164, 6,
+    /// ];
+    /// let config = Config::decode(&v1).ok().flatten().unwrap();
+    /// assert_eq!(config.net_id, 0x13);
+    /// assert_eq!(config.nwkskey, Some(0x0102030405060708090a0b0c0d0e0f10));
+    /// assert_eq!(config.appskey, Some(0x1112131415161718191a1b1c1d1e1f20));
+    /// assert_eq!(config.iccid, Some(0x8944_0000_0000_0001));
+    /// assert_eq!(config.send_frequency_ms, 900_000);
+    /// assert_eq!(config.network_server_host, Some(IpAddr::V4([192, 168, 0, 10])));
+    /// assert_eq!(config.network_server_port, 1700);
+    /// assert!(config.is_complete());
+    /// assert_eq!(config.nb_trans, Config::new().nb_trans);
+    ///
+    /// // As written by the current version, which must remain decodable
+    /// let v2 = [
+    ///     2, 19, 0, 0, 0, 0, 0, 0, 128, 238, 54, 0, 0, 0, 158, 6, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0,
+    ///     0, 0, 0, 0, 0, 0, 51, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16, 14, 72, 13, 4, 1, 192, 39, 9, 0, 0,
+    /// ];
+    /// let config = Config::decode(&v2).ok().flatten().unwrap();
+    /// let mut buf = [0; MAX_ENCODED_LEN];
+    /// assert_eq!(config.encode(&mut buf).unwrap(), &v2);
+    /// assert_eq!(Config::new().encode(&mut buf).unwrap(), &v2);
+    ///
+    /// assert!(Config::decode(&[0xff; 64]).ok().flatten().is_none());
+    /// assert_eq!(Config::decode(&[3, 0]).err(), Some(DecodeError::UnknownVersion));
+    /// assert_eq!(Config::decode(&v1[..32]).err(), Some(DecodeError::Corrupt));
+    /// ```
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, DecodeError> {
+        if matches!(bytes.first(), None | Some(&ERASED)) {
+            return Ok(None);
+        }
+        let (version, fields) =
+            take_from_bytes::<Version>(bytes).map_err(|_| DecodeError::UnknownVersion)?;
+        match version {
+            Version::V1 => take_from_bytes::<v1::Config>(fields).map(|(v1, _)| v1.into()),
+            Version::V2 => take_from_bytes::<Config>(fields).map(|(config, _)| config),
+            Version::Invalid => return Err(DecodeError::UnknownVersion),
+        }
+        .map(Some)
+        .map_err(|_| DecodeError::Corrupt)
+    }
+
+    /// Encode the configuration as the current version, returning the bytes
+    /// of the buffer used.
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], postcard::Error> {
+        to_slice(&(Version::CURRENT, self), buf)
+    }
+
+    /// Load our configuration, which is new should none have been saved.
+    ///
+    /// ```
+    /// use app::config::Config;
+    /// use app::flash::RamFlash;
+    /// use app::host::IpAddr;
+    /// use embedded_storage::nor_flash::NorFlash;
+    ///
+    /// let mut flash = RamFlash::<20480>::new();
+    /// assert!(!Config::load(&mut flash).unwrap().is_complete());
+    ///
+    /// // As saved by our first release, padded to whole words
+    /// let mut v1 = [0xff; 60];
+    /// v1[..59].copy_from_slice(&[
+    ///     0, 19, 0, 0, 0, 1, 16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 1, 32, 31, 30,
+    ///     29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 1, 1, 0, 0, 0, 0, 0, 68, 137, 160,
+    ///     187, 13, 0, 1, 192, 168, 0, 10, 164, 6,
+    /// ]);
+    /// flash.try_write(16384, &v1).unwrap();
+    /// let mut config = Config::load(&mut flash).unwrap();
+    /// assert_eq!(config.network_server_port, 1700);
+    ///
+    /// // Saved, and saved again, leaving what was saved first should we lose
+    /// // power while saving the second time
+    /// config.save(&mut flash).unwrap();
+    /// config.network_server_host = Some(IpAddr::V4([10, 0, 0, 1]));
+    /// config.save(&mut flash).unwrap();
+    /// let loaded = Config::load(&mut flash).unwrap();
+    /// assert_eq!(loaded.network_server_host, Some(IpAddr::V4([10, 0, 0, 1])));
+    /// assert_eq!(loaded.network_server_port, 1700);
+    /// flash.try_erase(16384, 20480).unwrap();
+    /// let loaded = Config::load(&mut flash).unwrap();
+    /// assert_eq!(loaded.network_server_host, Some(IpAddr::V4([192, 168, 0, 10])));
+    /// ```
+    pub fn load<F>(flash: &mut F) -> Result<Self, ConfigError>
+    where
+        F: NorFlash,
+    {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let len = Self::slots::<F>()
+            .load(flash, &mut buf)?
+            .map(|body| body.len());
+        let bytes = match len {
+            Some(len) => &buf[..len],
+            None => {
+                flash
+                    .try_read(LEGACY_PAGE * F::ERASE_SIZE as u32, &mut buf)
+                    .map_err(|_| ConfigError::Read)?;
+                &buf[..]
+            }
+        };
+        match Self::decode(bytes) {
+            Ok(config) => Ok(config.unwrap_or_default()),
+            Err(_) => Err(ConfigError::Unrecognised),
+        }
+    }
+
+    pub fn save<F>(&self, flash: &mut F) -> Result<(), ConfigError>
+    where
+        F: NorFlash,
+    {
+        let mut buf = [0u8; RECORD_LEN];
+        let len = self
+            .encode(&mut buf[HEADER_LEN..])
+            .map_err(|_| ConfigError::Overflow)?
+            .len();
+        Self::slots::<F>().save(flash, &mut buf, len)
+    }
+
+    // What our first release saved remains until we've first saved
+    // successfully, as our first slot, which is saved to first, is a page that
+    // it didn't use.
+
+    fn slots<F>() -> Slots
+    where
+        F: NorFlash,
+    {
+        let first = FIRST_SLOT_PAGE * F::ERASE_SIZE as u32;
+        Slots::new([first, first + F::ERASE_SIZE as u32])
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
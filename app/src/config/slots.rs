@@ -0,0 +1,215 @@
+//! Our configuration is saved to two flash pages in turn, so that should we
+//! lose power while saving, the other still holds what we last saved. Each
+//! page holds a record, headed by its sequence number, its length, and a
+//! CRC32 of both along with its body, all little endian. A page is only taken
+//! as holding a record when its CRC matches, and the newest record is that
+//! with the highest sequence number.
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use super::ConfigError;
+
+/// The length of a record's header, which precedes its body.
+pub const HEADER_LEN: usize = 12;
+
+// The CRC-32 used by Ethernet and zip, computed bitwise given how little we
+// check. CRCs may be continued from one call to the next.
+
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// A record, as found in a slot.
+
+#[derive(Clone, Copy)]
+struct Record {
+    slot: usize,
+    sequence: u32,
+    len: usize,
+}
+
+/// Two slots at the given offsets, each a flash page.
+///
+/// ```
+/// use app::config::{slots::{Slots, HEADER_LEN}, ConfigError};
+/// use app::flash::RamFlash;
+/// use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+///
+/// let mut flash = RamFlash::<8192>::new();
+/// let slots = Slots::new([0, 4096]);
+/// let mut buf = [0; 64];
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(None));
+///
+/// // Saving alternates between the slots
+/// let save = |flash: &mut RamFlash<8192>, body: &[u8]| {
+///     let mut buf = [0; 64];
+///     buf[HEADER_LEN..HEADER_LEN + body.len()].copy_from_slice(body);
+///     slots.save(flash, &mut buf, body.len())
+/// };
+/// let mut header = [0; 8];
+/// save(&mut flash, b"first").unwrap();
+/// flash.try_read(0, &mut header).unwrap();
+/// assert_eq!(header, [0, 0, 0, 0, 5, 0, 0, 0]); // Sequence and length
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"first"[..])));
+/// save(&mut flash, b"second").unwrap();
+/// flash.try_read(4096, &mut header).unwrap();
+/// assert_eq!(header, [1, 0, 0, 0, 6, 0, 0, 0]);
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"second"[..])));
+/// save(&mut flash, b"third").unwrap();
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"third"[..])));
+///
+/// // A record that is corrupt, or partly written, is passed over
+/// flash.try_write(HEADER_LEN as u32, &[0; 4]).unwrap();
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"second"[..])));
+/// flash.try_erase(0, 4096).unwrap();
+/// flash.try_write(0, &[2, 0, 0, 0, 5, 0, 0, 0]).unwrap();
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"second"[..])));
+///
+/// // The next save replaces it
+/// save(&mut flash, b"fourth").unwrap();
+/// assert_eq!(slots.load(&mut flash, &mut buf), Ok(Some(&b"fourth"[..])));
+/// flash.try_read(0, &mut header).unwrap();
+/// assert_eq!(header[..4], [2, 0, 0, 0]);
+///
+/// // Bodies must fit the buffer given
+/// let mut small = [0; 4];
+/// assert_eq!(slots.load(&mut flash, &mut small), Ok(None));
+///
+/// // And records must fit a page, and the buffer once padded
+/// let mut large = [0; 4100];
+/// assert_eq!(slots.save(&mut flash, &mut large, 4096 - HEADER_LEN + 1), Err(ConfigError::Overflow));
+/// assert_eq!(slots.save(&mut flash, &mut [0; HEADER_LEN], 1), Err(ConfigError::Overflow));
+///
+/// // Failing flash memory is reported
+/// let mut flash = RamFlash::<4096>::new();
+/// assert_eq!(slots.load(&mut flash, &mut buf), Err(ConfigError::Read));
+/// ```
+pub struct Slots {
+    offsets: [u32; 2],
+}
+
+impl Slots {
+    /// The first slot is written to when neither holds a record.
+    pub const fn new(offsets: [u32; 2]) -> Self {
+        Slots { offsets }
+    }
+
+    // Read a slot's header, and check its body's CRC a little at a time,
+    // returning the record should it be intact and fit within `max_len`.
+
+    fn scan<F>(
+        &self,
+        flash: &mut F,
+        slot: usize,
+        max_len: usize,
+    ) -> Result<Option<Record>, ConfigError>
+    where
+        F: ReadNorFlash,
+    {
+        let offset = self.offsets[slot];
+        let mut header = [0; HEADER_LEN];
+        flash
+            .try_read(offset, &mut header)
+            .map_err(|_| ConfigError::Read)?;
+        let field =
+            |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+        let (sequence, len, expected) = (field(0), field(4) as usize, field(8));
+        if len > max_len {
+            return Ok(None);
+        }
+
+        let mut crc = crc32(0, &header[..8]);
+        let mut chunk = [0; 32];
+        let mut read = 0;
+        while read < len {
+            let chunk = &mut chunk[..(len - read).min(32)];
+            flash
+                .try_read(offset + (HEADER_LEN + read) as u32, chunk)
+                .map_err(|_| ConfigError::Read)?;
+            crc = crc32(crc, chunk);
+            read += chunk.len();
+        }
+        Ok((crc == expected).then_some(Record {
+            slot,
+            sequence,
+            len,
+        }))
+    }
+
+    fn newest<F>(&self, flash: &mut F, max_len: usize) -> Result<Option<Record>, ConfigError>
+    where
+        F: ReadNorFlash,
+    {
+        let first = self.scan(flash, 0, max_len)?;
+        let second = self.scan(flash, 1, max_len)?;
+        Ok(match (first, second) {
+            (Some(first), Some(second)) if second.sequence > first.sequence => Some(second),
+            (Some(first), _) => Some(first),
+            (None, second) => second,
+        })
+    }
+
+    /// Read the body of the newest record into the buffer, returning it.
+    pub fn load<'a, F>(
+        &self,
+        flash: &mut F,
+        buf: &'a mut [u8],
+    ) -> Result<Option<&'a [u8]>, ConfigError>
+    where
+        F: ReadNorFlash,
+    {
+        match self.newest(flash, buf.len())? {
+            Some(record) => {
+                let body = &mut buf[..record.len];
+                flash
+                    .try_read(self.offsets[record.slot] + HEADER_LEN as u32, body)
+                    .map_err(|_| ConfigError::Read)?;
+                Ok(Some(body))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Save a record whose body of `len` bytes follows `HEADER_LEN` bytes of
+    /// the buffer, which must leave room for the record to be padded to
+    /// whole flash words, and the record must fit a flash page. The slot not
+    /// holding the newest record is erased and written to.
+    pub fn save<F>(&self, flash: &mut F, buf: &mut [u8], len: usize) -> Result<(), ConfigError>
+    where
+        F: NorFlash,
+    {
+        let max_len = F::ERASE_SIZE - HEADER_LEN;
+        let end = (HEADER_LEN + len).div_ceil(F::WRITE_SIZE) * F::WRITE_SIZE;
+        if len > max_len || end > buf.len() {
+            return Err(ConfigError::Overflow);
+        }
+        let (slot, sequence) = match self.newest(flash, max_len)? {
+            Some(newest) => (1 - newest.slot, newest.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        buf[0..4].copy_from_slice(&sequence.to_le_bytes());
+        buf[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        let crc = crc32(crc32(0, &buf[..8]), &buf[HEADER_LEN..HEADER_LEN + len]);
+        buf[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        let offset = self.offsets[slot];
+        flash
+            .try_erase(offset, offset + F::ERASE_SIZE as u32)
+            .map_err(|_| ConfigError::Erase)?;
+        flash
+            .try_write(offset, &buf[..end])
+            .map_err(|_| ConfigError::Write)
+    }
+}
@@ -0,0 +1,33 @@
+//! The configuration as first released, activating by personalisation with
+//! an IPv4 network server.
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::IpAddr;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub net_id: u32,
+    pub nwkskey: Option<u128>,
+    pub appskey: Option<u128>,
+    pub iccid: Option<u64>,
+    pub send_frequency_ms: u32,
+    pub network_server_host: Option<[u8; 4]>,
+    pub network_server_port: u16,
+}
+
+/// Everything that has been added since takes its default.
+impl From<Config> for super::Config {
+    fn from(v1: Config) -> Self {
+        super::Config {
+            net_id: v1.net_id,
+            nwkskey: v1.nwkskey,
+            appskey: v1.appskey,
+            iccid: v1.iccid,
+            send_frequency_ms: v1.send_frequency_ms,
+            network_server_host: v1.network_server_host.map(IpAddr::V4),
+            network_server_port: v1.network_server_port,
+            ..super::Config::new()
+        }
+    }
+}
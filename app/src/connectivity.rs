@@ -0,0 +1,212 @@
+//! Our connection to the cellular network. Attaching can fail for want of
+//! coverage or a SIM, and a socket can fail once attached. Rather than give
+//! up, we power the modem down and try again later, backing off exponentially
+//! so as not to drain the battery. A socket that fails is re-created once
+//! before we regard the connection as lost.
+//!
+//! The state machine is driven by events, returning the action for the device
+//! to take, which in turn leads to further events.
+
+/// How long to wait before attaching again after a failure. Successive
+/// failures double the delay, up to a maximum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffPolicy {
+    pub initial_ms: u32,
+    pub max_ms: u32,
+}
+
+impl BackoffPolicy {
+    /// The delay following a number of consecutive failures, the first
+    /// failure being 1.
+    ///
+    /// ```
+    /// use app::connectivity::BackoffPolicy;
+    ///
+    /// let policy = BackoffPolicy { initial_ms: 30_000, max_ms: 100_000 };
+    /// assert_eq!(policy.delay_ms(1), 30_000);
+    /// assert_eq!(policy.delay_ms(2), 60_000);
+    /// assert_eq!(policy.delay_ms(3), 100_000);
+    /// assert_eq!(policy.delay_ms(255), 100_000);
+    /// ```
+    pub fn delay_ms(&self, failures: u8) -> u32 {
+        let doublings = u32::from(failures.saturating_sub(1)).min(31);
+        self.initial_ms
+            .saturating_mul(1 << doublings)
+            .min(self.max_ms)
+    }
+}
+
+impl Default for BackoffPolicy {
+    /// Starting at 30 seconds, and at most 4 hours.
+    fn default() -> Self {
+        BackoffPolicy {
+            initial_ms: 30_000,
+            max_ms: 4 * 60 * 60 * 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// The modem is powered down.
+    Off,
+    /// The modem is powered up and attaching to the network.
+    Attaching,
+    /// We're attached and have, or are about to have, a socket.
+    Attached,
+    /// The modem is powered down until the given time.
+    Backoff { retry_at_ms: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// We'd like to be connected e.g. to send an uplink.
+    Connect,
+    /// We'd like to be disconnected e.g. to conserve our battery.
+    Disconnect,
+    /// The modem has attached to the network.
+    Attached,
+    /// The modem could not attach to the network.
+    AttachFailed,
+    /// The socket could not be created, or could not be written to.
+    LinkFailed,
+    /// An uplink was sent, showing that the connection works.
+    Sent,
+}
+
+/// What the device should do next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    None,
+    /// Power up the modem and wait for it to attach, then report whether it did.
+    Attach,
+    /// Create a new socket, reporting `LinkFailed` if that fails.
+    OpenSocket,
+    /// Drop any socket and power down the modem.
+    PowerOff,
+}
+
+/// Times are milliseconds since an arbitrary epoch, such as boot.
+///
+/// ```
+/// use app::connectivity::{Action, BackoffPolicy, Connectivity, Event, State};
+///
+/// let mut c = Connectivity::new(BackoffPolicy { initial_ms: 1000, max_ms: 4000 });
+/// assert_eq!(c.state(), State::Off);
+///
+/// // Off -> Attaching -> Attached
+/// assert_eq!(c.handle(Event::Connect, 0), Action::Attach);
+/// assert_eq!(c.state(), State::Attaching);
+/// assert_eq!(c.handle(Event::Attached, 0), Action::OpenSocket);
+/// assert_eq!(c.state(), State::Attached);
+/// assert_eq!(c.handle(Event::Connect, 0), Action::None);
+///
+/// // A socket is re-created once, then we back off
+/// assert_eq!(c.handle(Event::LinkFailed, 0), Action::OpenSocket);
+/// assert_eq!(c.state(), State::Attached);
+/// assert_eq!(c.handle(Event::LinkFailed, 0), Action::PowerOff);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 1000 });
+///
+/// // Backoff -> Attaching once the time has come, backing off further on failure
+/// assert_eq!(c.handle(Event::Connect, 999), Action::None);
+/// assert_eq!(c.handle(Event::Connect, 1000), Action::Attach);
+/// assert_eq!(c.state(), State::Attaching);
+/// assert_eq!(c.handle(Event::AttachFailed, 1000), Action::PowerOff);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 3000 });
+/// assert_eq!(c.handle(Event::Connect, 3000), Action::Attach);
+/// assert_eq!(c.handle(Event::AttachFailed, 3000), Action::PowerOff);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 7000 });
+/// assert_eq!(c.handle(Event::Connect, 7000), Action::Attach);
+/// assert_eq!(c.handle(Event::AttachFailed, 7000), Action::PowerOff);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 11000 }); // At most 4s
+///
+/// // Sending resets the backoff
+/// assert_eq!(c.handle(Event::Connect, 11000), Action::Attach);
+/// assert_eq!(c.handle(Event::Attached, 11000), Action::OpenSocket);
+/// assert_eq!(c.handle(Event::Sent, 11000), Action::None);
+/// assert_eq!(c.handle(Event::LinkFailed, 12000), Action::OpenSocket);
+/// assert_eq!(c.handle(Event::LinkFailed, 12000), Action::PowerOff);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 13000 });
+///
+/// // Events that don't apply to a state are ignored
+/// assert_eq!(c.handle(Event::Attached, 13000), Action::None);
+/// assert_eq!(c.handle(Event::Sent, 13000), Action::None);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 13000 });
+///
+/// // Disconnecting powers off the modem, though a backoff is retained
+/// assert_eq!(c.handle(Event::Disconnect, 13000), Action::None);
+/// assert_eq!(c.state(), State::Backoff { retry_at_ms: 13000 });
+/// assert_eq!(c.handle(Event::Connect, 13000), Action::Attach);
+/// assert_eq!(c.handle(Event::Attached, 13000), Action::OpenSocket);
+/// assert_eq!(c.handle(Event::Disconnect, 13000), Action::PowerOff);
+/// assert_eq!(c.state(), State::Off);
+/// assert_eq!(c.handle(Event::Disconnect, 13000), Action::None);
+/// ```
+pub struct Connectivity {
+    policy: BackoffPolicy,
+    state: State,
+    failures: u8,
+    socket_recreated: bool,
+}
+
+impl Connectivity {
+    pub fn new(policy: BackoffPolicy) -> Self {
+        Connectivity {
+            policy,
+            state: State::Off,
+            failures: 0,
+            socket_recreated: false,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.state == State::Attached
+    }
+
+    pub fn handle(&mut self, event: Event, now_ms: u64) -> Action {
+        match (self.state, event) {
+            (State::Off, Event::Connect) | (State::Attaching, Event::Connect) => self.attach(),
+            (State::Backoff { retry_at_ms }, Event::Connect) if now_ms >= retry_at_ms => {
+                self.attach()
+            }
+            (State::Attaching, Event::Attached) => {
+                self.state = State::Attached;
+                self.socket_recreated = false;
+                Action::OpenSocket
+            }
+            (State::Attaching, Event::AttachFailed) => self.back_off(now_ms),
+            (State::Attached, Event::LinkFailed) if !self.socket_recreated => {
+                self.socket_recreated = true;
+                Action::OpenSocket
+            }
+            (State::Attached, Event::LinkFailed) => self.back_off(now_ms),
+            (State::Attaching, Event::Disconnect) | (State::Attached, Event::Disconnect) => {
+                self.state = State::Off;
+                Action::PowerOff
+            }
+            (State::Attached, Event::Sent) => {
+                self.failures = 0;
+                self.socket_recreated = false;
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn attach(&mut self) -> Action {
+        self.state = State::Attaching;
+        Action::Attach
+    }
+
+    fn back_off(&mut self, now_ms: u64) -> Action {
+        self.failures = self.failures.saturating_add(1);
+        self.state = State::Backoff {
+            retry_at_ms: now_ms + u64::from(self.policy.delay_ms(self.failures)),
+        };
+        Action::PowerOff
+    }
+}
@@ -0,0 +1,229 @@
+//! Our crash log survives a reset, but not a loss of power, by living in RAM
+//! that isn't initialised as we start. Should we panic, its message and
+//! location are noted there before we reset, and reported once we've started
+//! again.
+
+use core::fmt::{self, Display, Write};
+use heapless::String;
+
+use crate::codec::{EncodeError, Payload, PayloadCodec};
+
+/// The most of a panic's file name and message that we retain.
+pub const MAX_FILE_LEN: usize = 32;
+pub const MAX_MESSAGE_LEN: usize = 64;
+
+// Distinguishes a crash log from whatever RAM held as we powered on.
+
+const MAGIC: u32 = 0x4352_4153;
+
+/// A panic, as noted in our crash log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crash {
+    /// The number of times we'd reset since powering on when we panicked.
+    pub resets: u32,
+    pub file: String<MAX_FILE_LEN>,
+    pub line: u32,
+    pub message: String<MAX_MESSAGE_LEN>,
+}
+
+/// Laid out so that any contents are valid, given that it isn't initialised.
+///
+/// ```
+/// use app::crash::CrashLog;
+///
+/// let mut log = CrashLog::new();
+/// log.started();
+/// assert_eq!(log.crash(), None);
+/// log.record("src/main.rs", 42, &"called `Option::unwrap()` on a `None` value");
+///
+/// // Reported once we've started again
+/// log.started();
+/// let crash = log.crash().unwrap();
+/// assert_eq!(crash.resets, 1);
+/// assert_eq!(crash.file, "src/main.rs");
+/// assert_eq!(crash.line, 42);
+/// assert_eq!(crash.message, "called `Option::unwrap()` on a `None` value");
+///
+/// log.clear();
+/// log.started();
+/// assert_eq!(log.crash(), None);
+/// assert_eq!(log.resets(), 3);
+///
+/// // Leaving our console is noted until we've started again
+/// log.leaving_console();
+/// log.started();
+/// assert!(log.left_console());
+/// assert!(!log.left_console());
+///
+/// // Long file names retain their end, and long messages their start
+/// log.record(&"a/".repeat(20), 1, &"é".repeat(40));
+/// let crash = log.crash().unwrap();
+/// assert_eq!(crash.file.as_str(), "a/".repeat(16));
+/// assert_eq!(crash.message.as_str(), "é".repeat(32));
+/// ```
+#[repr(C)]
+pub struct CrashLog {
+    magic: u32,
+    resets: u32,
+    crashed: u8,
+    console: u8,
+    file_len: u8,
+    message_len: u8,
+    line: u32,
+    file: [u8; MAX_FILE_LEN],
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+// Retains as much of a message as fits, ending on a character boundary.
+
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Truncating<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > self.buf.len() {
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.buf[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+fn to_string<const N: usize>(bytes: &[u8], len: u8) -> String<N> {
+    let bytes = &bytes[..usize::from(len).min(bytes.len())];
+    let mut s = String::new();
+    let _ = s.push_str(core::str::from_utf8(bytes).unwrap_or(""));
+    s
+}
+
+impl CrashLog {
+    pub const fn new() -> Self {
+        CrashLog {
+            magic: MAGIC,
+            resets: 0,
+            crashed: 0,
+            console: 0,
+            file_len: 0,
+            message_len: 0,
+            line: 0,
+            file: [0; MAX_FILE_LEN],
+            message: [0; MAX_MESSAGE_LEN],
+        }
+    }
+
+    /// Note that we've started, initialising the log should we have powered
+    /// on.
+    pub fn started(&mut self) {
+        if self.magic != MAGIC {
+            *self = Self::new();
+        } else {
+            self.resets = self.resets.wrapping_add(1);
+        }
+    }
+
+    /// The number of times we've reset since powering on.
+    pub fn resets(&self) -> u32 {
+        self.resets
+    }
+
+    pub fn record(&mut self, file: &str, line: u32, message: &dyn Display) {
+        let mut start = file.len().saturating_sub(MAX_FILE_LEN);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        let file = &file[start..];
+        self.file[..file.len()].copy_from_slice(file.as_bytes());
+        self.file_len = file.len() as u8;
+
+        let mut writer = Truncating {
+            buf: &mut self.message,
+            len: 0,
+        };
+        let _ = write!(writer, "{}", message);
+        self.message_len = writer.len as u8;
+
+        self.line = line;
+        self.crashed = 1;
+    }
+
+    /// The crash noted before we last reset, if any.
+    pub fn crash(&self) -> Option<Crash> {
+        if self.magic != MAGIC || self.crashed == 0 {
+            return None;
+        }
+        Some(Crash {
+            resets: self.resets.wrapping_sub(1),
+            file: to_string(&self.file, self.file_len),
+            line: self.line,
+            message: to_string(&self.message, self.message_len),
+        })
+    }
+
+    /// Forget our crash, once reported.
+    pub fn clear(&mut self) {
+        self.crashed = 0;
+    }
+
+    /// Note that we're resetting on leaving our console, so that the reset
+    /// isn't taken for a panic.
+    pub fn leaving_console(&mut self) {
+        self.console = 1;
+    }
+
+    /// Whether we last reset on leaving our console, forgetting it once asked.
+    pub fn left_console(&mut self) -> bool {
+        let left = self.magic == MAGIC && self.console != 0;
+        self.console = 0;
+        left
+    }
+}
+
+impl Default for CrashLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A crash is laid out as follows, all big endian:
+///
+/// Start |   End | Description
+///     0 |     3 | Resets since powering on
+///     4 |     7 | Line
+///     8 |     8 | File name length (N)
+///     9 | 8 + N | File name
+/// 9 + N |       | Message
+///
+/// ```
+/// use app::codec::{Payload, PayloadCodec};
+/// use app::crash::{Crash, CrashCodec};
+///
+/// let mut payload = Payload::new();
+/// let crash = Crash { resets: 2, file: "main.rs".into(), line: 42, message: "oops".into() };
+/// CrashCodec.encode(&crash, &mut payload).unwrap();
+/// assert_eq!(
+///     &payload[..],
+///     &[0, 0, 0, 2, 0, 0, 0, 42, 7, b'm', b'a', b'i', b'n', b'.', b'r', b's', b'o', b'o', b'p', b's']
+/// );
+/// ```
+pub struct CrashCodec;
+
+impl PayloadCodec<Crash> for CrashCodec {
+    fn encode(&self, value: &Crash, payload: &mut Payload) -> Result<(), EncodeError> {
+        let len = 4 + 4 + 1 + value.file.len() + value.message.len();
+        if payload.len() + len > payload.capacity() {
+            return Err(EncodeError::Overflow);
+        }
+        let _ = payload.extend_from_slice(&value.resets.to_be_bytes());
+        let _ = payload.extend_from_slice(&value.line.to_be_bytes());
+        let _ = payload.push(value.file.len() as u8);
+        let _ = payload.extend_from_slice(value.file.as_bytes());
+        let _ = payload.extend_from_slice(value.message.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Diagnostics of the modem and the network, sent periodically on their own
+//! FPort so that we can tell from the server why a device went quiet. Values
+//! are polled for with `AT+CESQ`, `AT+CEREG?`, `AT%XMONITOR` and `AT%CONEVAL`
+//! each time diagnostics are gathered, and any of them may be unavailable.
+
+use crate::codec::{EncodeError, Payload, PayloadCodec};
+
+/// The FPort on which diagnostics are sent.
+pub const DIAGNOSTICS_F_PORT: u8 = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    pub rsrp_dbm: Option<i16>,
+    /// In tenths of a dB.
+    pub rsrq_db10: Option<i16>,
+    pub snr_db: Option<i8>,
+    pub cell_id: Option<u32>,
+    pub tac: Option<u16>,
+    pub band: Option<u8>,
+    pub ce_level: Option<u8>,
+    /// How long the last attach took.
+    pub attach_ms: Option<u32>,
+    /// Whether the modem accepted our requests for power saving when we last
+    /// attached.
+    pub power_saving_accepted: Option<bool>,
+}
+
+// The fields of an AT command response with the given prefix, unquoted. Commas
+// within quotes, as may be found in operator names, don't separate fields.
+
+fn fields<'a>(line: &'a str, prefix: &str) -> Option<impl Iterator<Item = &'a str>> {
+    let mut quoted = false;
+    let fields = line.strip_prefix(prefix)?.split(move |c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ',' && !quoted
+    });
+    Some(fields.map(|field| field.trim().trim_matches('"')))
+}
+
+fn nth<'a>(fields: &mut impl Iterator<Item = &'a str>, n: usize) -> Option<&'a str> {
+    fields.nth(n).filter(|field| !field.is_empty())
+}
+
+// RSRP and RSRQ are reported as indices, with 255 being unknown.
+
+fn rsrp_dbm(index: Option<&str>) -> Option<i16> {
+    let index = index?.parse::<i16>().ok().filter(|index| *index != 255)?;
+    Some(index - 140)
+}
+
+fn rsrq_db10(index: Option<&str>) -> Option<i16> {
+    let index = index?.parse::<i16>().ok().filter(|index| *index != 255)?;
+    Some(index * 5 - 195)
+}
+
+impl Diagnostics {
+    /// Note the values of a line of a response to any of our AT commands.
+    /// Lines that aren't recognised are ignored.
+    ///
+    /// ```
+    /// use app::diagnostics::Diagnostics;
+    ///
+    /// let mut diagnostics = Diagnostics::default();
+    /// diagnostics.update("+CESQ: 99,99,255,255,23,54");
+    /// diagnostics.update("+CEREG: 5,1,\"0140\",\"0A0B0C0D\",9,,,\"11100000\",\"11100000\"");
+    /// diagnostics.update(
+    ///     "%XMONITOR: 1,\"Operator, Inc.\",\"OP\",\"26295\",\"00B7\",9,20,\"00011B07\",7,2300,63,39,\"\",\"11100000\",\"00000110\"",
+    /// );
+    /// diagnostics.update("%CONEVAL: 0,1,5,8,2,14,\"011B0780\",\"26201\",7,1575,3,1,1,23,16,32,130");
+    /// diagnostics.update("OK");
+    /// assert_eq!(
+    ///     diagnostics,
+    ///     Diagnostics {
+    ///         rsrp_dbm: Some(-86),
+    ///         rsrq_db10: Some(-80),
+    ///         snr_db: Some(15),
+    ///         cell_id: Some(0x00011b07),
+    ///         tac: Some(0x00b7),
+    ///         band: Some(20),
+    ///         ce_level: Some(1),
+    ///         attach_ms: None,
+    ///         power_saving_accepted: None,
+    ///     }
+    /// );
+    ///
+    /// // Values that are unknown remain so
+    /// let mut diagnostics = Diagnostics::default();
+    /// diagnostics.update("+CESQ: 99,99,255,255,255,255");
+    /// diagnostics.update("+CEREG: 5,2");
+    /// diagnostics.update("%XMONITOR: 2");
+    /// assert_eq!(diagnostics, Diagnostics::default());
+    /// ```
+    pub fn update(&mut self, line: &str) {
+        if let Some(mut fields) = fields(line, "+CESQ:") {
+            self.rsrq_db10 = rsrq_db10(nth(&mut fields, 4));
+            self.rsrp_dbm = rsrp_dbm(nth(&mut fields, 0));
+        } else if let Some(mut fields) = fields(line, "+CEREG:") {
+            if let Some(tac) = nth(&mut fields, 2).and_then(|f| u16::from_str_radix(f, 16).ok()) {
+                self.tac = Some(tac);
+            }
+            if let Some(cell_id) = nth(&mut fields, 0).and_then(|f| u32::from_str_radix(f, 16).ok())
+            {
+                self.cell_id = Some(cell_id);
+            }
+        } else if let Some(mut fields) = fields(line, "%XMONITOR:") {
+            if let Some(tac) = nth(&mut fields, 4).and_then(|f| u16::from_str_radix(f, 16).ok()) {
+                self.tac = Some(tac);
+            }
+            if let Some(band) = nth(&mut fields, 1).and_then(|f| f.parse::<u8>().ok()) {
+                self.band = Some(band);
+            }
+            if let Some(cell_id) = nth(&mut fields, 0).and_then(|f| u32::from_str_radix(f, 16).ok())
+            {
+                self.cell_id = Some(cell_id);
+            }
+            // SNR is offset by 24, with 127 being unknown.
+            self.rsrp_dbm = self.rsrp_dbm.or(rsrp_dbm(nth(&mut fields, 2)));
+            let snr = nth(&mut fields, 0).and_then(|f| f.parse::<i8>().ok());
+            self.snr_db = snr.filter(|snr| *snr != 127).map(|snr| snr - 24);
+        } else if let Some(mut fields) = fields(line, "%CONEVAL:") {
+            if let Some(ce_level) = nth(&mut fields, 12).and_then(|f| f.parse::<u8>().ok()) {
+                self.ce_level = Some(ce_level);
+            }
+        }
+    }
+}
+
+/// Diagnostics are laid out as follows, all big endian, with unknown values
+/// being the most negative of signed fields and all bits set of others:
+///
+/// Start |   End | Description
+///     0 |     1 | RSRP (dBm)
+///     2 |     3 | RSRQ (dB) * 10
+///     4 |     4 | SNR (dB)
+///     5 |     8 | Cell ID
+///     9 |    10 | Tracking area code
+///    11 |    11 | Band
+///    12 |    12 | Coverage enhancement level
+///    13 |    16 | Duration of the last attach (ms)
+///    17 |    17 | Power saving requests accepted by the modem (1) or not (0)
+///
+/// ```
+/// use app::codec::{Payload, PayloadCodec};
+/// use app::diagnostics::{Diagnostics, DiagnosticsCodec};
+///
+/// let mut payload = Payload::new();
+/// let diagnostics = Diagnostics {
+///     rsrp_dbm: Some(-86),
+///     rsrq_db10: Some(-80),
+///     snr_db: Some(15),
+///     cell_id: Some(0x00011b07),
+///     tac: Some(0x00b7),
+///     band: Some(20),
+///     ce_level: Some(1),
+///     attach_ms: Some(2500),
+///     power_saving_accepted: Some(false),
+/// };
+/// DiagnosticsCodec.encode(&diagnostics, &mut payload).unwrap();
+/// assert_eq!(
+///     &payload[..],
+///     &[0xff, 0xaa, 0xff, 0xb0, 0x0f, 0x00, 0x01, 0x1b, 0x07, 0x00, 0xb7, 0x14, 0x01, 0x00, 0x00, 0x09, 0xc4, 0x00]
+/// );
+///
+/// let mut payload = Payload::new();
+/// DiagnosticsCodec.encode(&Diagnostics::default(), &mut payload).unwrap();
+/// assert_eq!(
+///     &payload[..],
+///     &[0x80, 0x00, 0x80, 0x00, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+/// );
+/// ```
+pub struct DiagnosticsCodec;
+
+const DIAGNOSTICS_LEN: usize = 18;
+
+impl PayloadCodec<Diagnostics> for DiagnosticsCodec {
+    fn encode(&self, value: &Diagnostics, payload: &mut Payload) -> Result<(), EncodeError> {
+        let mut bytes = [0; DIAGNOSTICS_LEN];
+        bytes[0..2].copy_from_slice(&value.rsrp_dbm.unwrap_or(i16::MIN).to_be_bytes());
+        bytes[2..4].copy_from_slice(&value.rsrq_db10.unwrap_or(i16::MIN).to_be_bytes());
+        bytes[4..5].copy_from_slice(&value.snr_db.unwrap_or(i8::MIN).to_be_bytes());
+        bytes[5..9].copy_from_slice(&value.cell_id.unwrap_or(u32::MAX).to_be_bytes());
+        bytes[9..11].copy_from_slice(&value.tac.unwrap_or(u16::MAX).to_be_bytes());
+        bytes[11] = value.band.unwrap_or(u8::MAX);
+        bytes[12] = value.ce_level.unwrap_or(u8::MAX);
+        bytes[13..17].copy_from_slice(&value.attach_ms.unwrap_or(u32::MAX).to_be_bytes());
+        bytes[17] = value.power_saving_accepted.map_or(u8::MAX, u8::from);
+        payload
+            .extend_from_slice(&bytes)
+            .map_err(|_| EncodeError::Overflow)
+    }
+}
@@ -0,0 +1,237 @@
+//! Downlinks are received from the network server after each uplink, in the
+//! spirit of a LoRaWAN class-A device. Frames are parsed and validated here
+//! so that the device need only hand us the bytes it read from its socket.
+
+use heapless::Vec;
+use lorawan_encoding::{
+    keys,
+    parser::{parse, AsPhyPayloadBytes, DataHeader, DataPayload, MHDRAble, MType, PhyPayload},
+};
+
+/// The maximum number of frames that we'll accept as having been lost
+/// between two downlinks, as per the LoRaWAN MAX_FCNT_GAP.
+pub const MAX_FCNT_GAP: u32 = 16384;
+
+/// The maximum size of a FRMPayload that we can receive.
+pub const MAX_FRM_PAYLOAD_LEN: usize = 242;
+
+/// The length of MHDR, DevAddr, FCtrl and FCnt preceding any FOpts.
+const FHDR_END: usize = 8;
+
+/// The length of the MIC at the end of a frame.
+const MIC_LEN: usize = 4;
+
+/// A downlink that has been validated and decrypted.
+#[derive(Debug, PartialEq)]
+pub struct Downlink {
+    pub fcnt: u32,
+    pub confirmed: bool,
+    pub ack: bool,
+    pub adr: bool,
+    pub f_pending: bool,
+    pub fopts: Vec<u8, 15>,
+    pub f_port: Option<u8>,
+    pub frm_payload: Vec<u8, MAX_FRM_PAYLOAD_LEN>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DownlinkError {
+    Malformed,
+    NotForUs,
+    InvalidMic,
+    InvalidFcnt,
+}
+
+/// Tracks the downlink frame counter so that we can reconstruct the full 32 bits
+/// of a counter from the 16 bits conveyed on air, and so that we reject replays.
+///
+/// ```
+/// use app::downlink::DownlinkCounter;
+///
+/// let counter = DownlinkCounter::new();
+/// assert_eq!(counter.next(0x0001), Some(0x0001));
+///
+/// let counter = DownlinkCounter::with_last(Some(0x0001_fff0));
+/// assert_eq!(counter.next(0xfff1), Some(0x0001_fff1));
+/// assert_eq!(counter.next(0x0002), Some(0x0002_0002));
+/// assert_eq!(counter.next(0xfff0), None); // A replay
+/// assert_eq!(counter.next(0x4000), None); // Too many frames lost
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DownlinkCounter {
+    last: Option<u32>,
+}
+
+impl DownlinkCounter {
+    pub fn new() -> Self {
+        Self::with_last(None)
+    }
+
+    pub fn with_last(last: Option<u32>) -> Self {
+        DownlinkCounter { last }
+    }
+
+    /// The counter of the last downlink accepted.
+    pub fn last(&self) -> Option<u32> {
+        self.last
+    }
+
+    /// Return the full counter that a received 16 bit counter represents, or
+    /// `None` if it represents a frame we've already seen, or too many have
+    /// been lost.
+    pub fn next(&self, fcnt: u16) -> Option<u32> {
+        match self.last {
+            None => Some(u32::from(fcnt)),
+            Some(last) => {
+                let mut candidate = (last & 0xffff_0000) | u32::from(fcnt);
+                if candidate <= last {
+                    candidate = candidate.checked_add(0x0001_0000)?;
+                }
+                if candidate - last <= MAX_FCNT_GAP {
+                    Some(candidate)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Record that a downlink with the given counter has been accepted.
+    pub fn accept(&mut self, fcnt: u32) {
+        self.last = Some(fcnt);
+    }
+}
+
+/// Parse a received PHYPayload, validating that it is a downlink for our
+/// DevAddr, that its MIC is correct for its frame counter, and decrypting its
+/// FRMPayload. The frame counter is only advanced for valid frames. Note that
+/// the bytes are decrypted in place.
+///
+/// ```
+/// use app::downlink::{decode_downlink, DownlinkCounter, DownlinkError};
+/// use lorawan_encoding::{creator::DataPayloadCreator, keys::AES128};
+///
+/// let dev_addr = 0x01020304_u32;
+/// let nwk_skey = 0x2B7E151628AED2A6ABF7158809CF4F3C_u128;
+/// let app_skey = 0x000102030405060708090A0B0C0D0E0F_u128;
+///
+/// let mut phy = DataPayloadCreator::new();
+/// phy.set_confirmed(true)
+///     .set_uplink(false)
+///     .set_f_port(2)
+///     .set_dev_addr(&dev_addr.to_le_bytes())
+///     .set_fcnt(1);
+/// let frame = phy
+///     .build(
+///         &[1, 2, 3],
+///         &[],
+///         &AES128(nwk_skey.to_le_bytes()),
+///         &AES128(app_skey.to_le_bytes()),
+///     )
+///     .unwrap();
+///
+/// let mut counter = DownlinkCounter::new();
+///
+/// let mut bytes = [0_u8; 64];
+/// bytes[..frame.len()].copy_from_slice(frame);
+/// let downlink = decode_downlink(&mut bytes[..frame.len()], dev_addr, nwk_skey, app_skey, &mut counter).unwrap();
+/// assert_eq!(downlink.fcnt, 1);
+/// assert!(downlink.confirmed);
+/// assert_eq!(downlink.f_port, Some(2));
+/// assert_eq!(&downlink.frm_payload[..], &[1, 2, 3]);
+/// assert_eq!(counter.last(), Some(1));
+///
+/// bytes[..frame.len()].copy_from_slice(frame);
+/// assert_eq!(
+///     decode_downlink(&mut bytes[..frame.len()], dev_addr, nwk_skey, app_skey, &mut counter),
+///     Err(DownlinkError::InvalidFcnt)
+/// );
+///
+/// bytes[..frame.len()].copy_from_slice(frame);
+/// assert_eq!(
+///     decode_downlink(&mut bytes[..frame.len()], dev_addr, 0_u128, app_skey, &mut DownlinkCounter::new()),
+///     Err(DownlinkError::InvalidMic)
+/// );
+///
+/// bytes[..frame.len()].copy_from_slice(frame);
+/// assert_eq!(
+///     decode_downlink(&mut bytes[..frame.len()], 0, nwk_skey, app_skey, &mut DownlinkCounter::new()),
+///     Err(DownlinkError::NotForUs)
+/// );
+///
+/// assert_eq!(
+///     decode_downlink(&mut [0_u8; 4], dev_addr, nwk_skey, app_skey, &mut DownlinkCounter::new()),
+///     Err(DownlinkError::Malformed)
+/// );
+/// ```
+pub fn decode_downlink(
+    bytes: &mut [u8],
+    dev_addr: u32,
+    nwk_skey: u128,
+    app_skey: u128,
+    counter: &mut DownlinkCounter,
+) -> Result<Downlink, DownlinkError> {
+    let data = match parse(bytes) {
+        Ok(PhyPayload::Data(DataPayload::Encrypted(data))) => data,
+        _ => return Err(DownlinkError::Malformed),
+    };
+
+    let confirmed = match data.mhdr().mtype() {
+        MType::UnconfirmedDataDown => false,
+        MType::ConfirmedDataDown => true,
+        _ => return Err(DownlinkError::NotForUs),
+    };
+
+    let fhdr = data.fhdr();
+    if fhdr.dev_addr().as_ref() != &dev_addr.to_le_bytes()[..] {
+        return Err(DownlinkError::NotForUs);
+    }
+    let fctrl = fhdr.fctrl();
+    let (ack, adr, f_pending) = (fctrl.ack(), fctrl.adr(), fctrl.f_pending());
+    let fopts_end = FHDR_END + usize::from(fctrl.f_opts_len());
+
+    let fcnt = counter
+        .next(fhdr.fcnt())
+        .ok_or(DownlinkError::InvalidFcnt)?;
+
+    let nwk_skey = keys::AES128(nwk_skey.to_le_bytes());
+    if !data.validate_mic(&nwk_skey, fcnt) {
+        return Err(DownlinkError::InvalidMic);
+    }
+
+    let f_port = data.f_port();
+    let decrypted = data
+        .decrypt(
+            Some(&nwk_skey),
+            Some(&keys::AES128(app_skey.to_le_bytes())),
+            fcnt,
+        )
+        .map_err(|_| DownlinkError::Malformed)?;
+
+    let phy = decrypted.as_bytes();
+    let frm_payload_start = if f_port.is_some() {
+        fopts_end + 1
+    } else {
+        fopts_end
+    };
+    let frm_payload_end = phy.len().saturating_sub(MIC_LEN);
+    if frm_payload_start > frm_payload_end {
+        return Err(DownlinkError::Malformed);
+    }
+
+    let downlink = Downlink {
+        fcnt,
+        confirmed,
+        ack,
+        adr,
+        f_pending,
+        fopts: Vec::from_slice(&phy[FHDR_END..fopts_end]).map_err(|_| DownlinkError::Malformed)?,
+        f_port,
+        frm_payload: Vec::from_slice(&phy[frm_payload_start..frm_payload_end])
+            .map_err(|_| DownlinkError::Malformed)?,
+    };
+
+    counter.accept(fcnt);
+
+    Ok(downlink)
+}
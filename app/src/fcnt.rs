@@ -0,0 +1,348 @@
+//! Frame counters must survive resets, otherwise the network server regards
+//! our uplinks as replays. Flash wears with each erase though, so rather
+//! than writing the counter for every frame, we reserve a block of counters
+//! ahead of time and only write when that block is used up. On boot we resume
+//! from the reservation, skipping at most a block's worth of counters.
+//! DevNonces, which must never be repeated when joining, are reserved in the
+//! same way and persisted along with our frame counters.
+//!
+//! Reservations are appended as records to a flash page, so that a page need
+//! only be erased once it is full. The last record written is the current one.
+//! Once a page is full, the next record is written to a second page before the
+//! first is erased, so that should we lose power while erasing, the second
+//! still holds our counters. The page holding the fewest records is then the
+//! current one.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::config::ConfigError;
+
+/// The length of a counter record in flash. A multiple of the flash word size.
+pub const RECORD_LEN: usize = 12;
+
+const ERASED: u32 = 0xffff_ffff;
+
+/// The counters as persisted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Counters {
+    /// The uplink frame counter that may be used next.
+    pub fcnt_up: u32,
+    /// The last downlink frame counter accepted, if any.
+    pub fcnt_down: Option<u32>,
+    /// The DevNonce that may be used next.
+    pub dev_nonce: u32,
+}
+
+impl Counters {
+    /// ```
+    /// use app::fcnt::{Counters, RECORD_LEN};
+    ///
+    /// let counters = Counters { fcnt_up: 0x0102, fcnt_down: None, dev_nonce: 0x0304 };
+    /// assert_eq!(
+    ///     counters.to_bytes(),
+    ///     [0x02, 0x01, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x04, 0x03, 0x00, 0x00]
+    /// );
+    /// assert_eq!(Counters::from_bytes(&counters.to_bytes()), Some(counters));
+    ///
+    /// let counters = Counters { fcnt_up: 16, fcnt_down: Some(3), dev_nonce: 0 };
+    /// assert_eq!(Counters::from_bytes(&counters.to_bytes()), Some(counters));
+    ///
+    /// assert_eq!(Counters::from_bytes(&[0xff; RECORD_LEN]), None);
+    /// ```
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        bytes[..4].copy_from_slice(&self.fcnt_up.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.fcnt_down.unwrap_or(ERASED).to_le_bytes());
+        bytes[8..].copy_from_slice(&self.dev_nonce.to_le_bytes());
+        bytes
+    }
+
+    /// Returns `None` for a record that has not been written i.e. is erased.
+    pub fn from_bytes(bytes: &[u8; RECORD_LEN]) -> Option<Self> {
+        let fcnt_up = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let fcnt_down = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let dev_nonce = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        if fcnt_up == ERASED {
+            None
+        } else {
+            Some(Counters {
+                fcnt_up,
+                fcnt_down: if fcnt_down == ERASED {
+                    None
+                } else {
+                    Some(fcnt_down)
+                },
+                dev_nonce,
+            })
+        }
+    }
+}
+
+/// Scan a page of records, returning the last one written along with the
+/// offset at which the next should be written. An offset of the page's length
+/// indicates that the page is full and must be erased.
+///
+/// ```
+/// use app::fcnt::{find_latest, Counters, RECORD_LEN};
+///
+/// let mut page = [0xff_u8; 4 * RECORD_LEN];
+/// assert_eq!(find_latest(&page), (None, 0));
+///
+/// page[..RECORD_LEN].copy_from_slice(&Counters { fcnt_up: 16, fcnt_down: None, dev_nonce: 0 }.to_bytes());
+/// page[RECORD_LEN..2 * RECORD_LEN].copy_from_slice(&Counters { fcnt_up: 32, fcnt_down: Some(1), dev_nonce: 0 }.to_bytes());
+/// assert_eq!(
+///     find_latest(&page),
+///     (Some(Counters { fcnt_up: 32, fcnt_down: Some(1), dev_nonce: 0 }), 2 * RECORD_LEN)
+/// );
+///
+/// for (i, record) in page.chunks_mut(RECORD_LEN).enumerate() {
+///     record.copy_from_slice(&Counters { fcnt_up: i as u32, fcnt_down: None, dev_nonce: 0 }.to_bytes());
+/// }
+/// assert_eq!(
+///     find_latest(&page),
+///     (Some(Counters { fcnt_up: 3, fcnt_down: None, dev_nonce: 0 }), 4 * RECORD_LEN)
+/// );
+/// ```
+pub fn find_latest(page: &[u8]) -> (Option<Counters>, usize) {
+    let mut latest = None;
+    for (i, record) in page.chunks_exact(RECORD_LEN).enumerate() {
+        let mut bytes = [0; RECORD_LEN];
+        bytes.copy_from_slice(record);
+        match Counters::from_bytes(&bytes) {
+            Some(counters) => latest = Some(counters),
+            None => return (latest, i * RECORD_LEN),
+        }
+    }
+    (latest, page.len() - page.len() % RECORD_LEN)
+}
+
+// The pages of our flash region holding our records, following that of
+// spilled uplinks.
+
+const FIRST_PAGE: u32 = 1;
+
+// Records are scanned this many at a time.
+
+const SCAN_RECORDS: usize = 64;
+
+/// Our counters, as saved to flash.
+///
+/// ```
+/// use app::fcnt::{CounterLog, Counters, RECORD_LEN};
+/// use app::flash::RamFlash;
+/// use embedded_storage::nor_flash::NorFlash;
+///
+/// let mut flash = RamFlash::<12288>::new();
+/// let (mut log, counters) = CounterLog::load(&mut flash).unwrap();
+/// assert_eq!(counters, None);
+///
+/// // Filling our first page, then moving on to the second
+/// let records = 4096 / RECORD_LEN as u32;
+/// for fcnt_up in 0..=records {
+///     log.save(&mut flash, &Counters { fcnt_up, fcnt_down: None, dev_nonce: 0 }).unwrap();
+/// }
+/// let (_, counters) = CounterLog::load(&mut flash).unwrap();
+/// assert_eq!(counters, Some(Counters { fcnt_up: records, fcnt_down: None, dev_nonce: 0 }));
+///
+/// // Should we lose power before erasing the first page, the second still
+/// // holds the latest
+/// for fcnt_up in 0..records {
+///     flash.try_write(4096 + fcnt_up * RECORD_LEN as u32, &Counters { fcnt_up, fcnt_down: None, dev_nonce: 0 }.to_bytes()).unwrap();
+/// }
+/// flash.try_erase(8192, 12288).unwrap();
+/// flash.try_write(8192, &Counters { fcnt_up: records, fcnt_down: Some(1), dev_nonce: 0 }.to_bytes()).unwrap();
+/// let (mut log, counters) = CounterLog::load(&mut flash).unwrap();
+/// assert_eq!(counters, Some(Counters { fcnt_up: records, fcnt_down: Some(1), dev_nonce: 0 }));
+/// log.save(&mut flash, &Counters { fcnt_up: records, fcnt_down: Some(2), dev_nonce: 0 }).unwrap();
+/// let (_, counters) = CounterLog::load(&mut flash).unwrap();
+/// assert_eq!(counters, Some(Counters { fcnt_up: records, fcnt_down: Some(2), dev_nonce: 0 }));
+///
+/// // Should our log not load, saving starts it afresh
+/// let mut log = CounterLog::unloaded();
+/// log.save(&mut flash, &Counters { fcnt_up: 1, fcnt_down: None, dev_nonce: 0 }).unwrap();
+/// let (_, counters) = CounterLog::load(&mut flash).unwrap();
+/// assert_eq!(counters, Some(Counters { fcnt_up: 1, fcnt_down: None, dev_nonce: 0 }));
+/// ```
+pub struct CounterLog {
+    page: usize,
+    next: u32,
+}
+
+impl CounterLog {
+    /// Load our log, returning the latest counters saved, if any.
+    pub fn load<F>(flash: &mut F) -> Result<(Self, Option<Counters>), ConfigError>
+    where
+        F: NorFlash,
+    {
+        let (first, first_next) = Self::scan(flash, 0)?;
+        let (second, second_next) = Self::scan(flash, 1)?;
+        let page = match (first_next, second_next) {
+            (_, 0) => 0,
+            (0, _) => 1,
+            _ if second_next < first_next => 1,
+            _ => 0,
+        };
+        let (latest, next) = if page == 0 {
+            (first, first_next)
+        } else {
+            (second, second_next)
+        };
+        Ok((CounterLog { page, next }, latest))
+    }
+
+    /// A log that couldn't be loaded, and so is taken to be full. Our first
+    /// save then starts afresh on the other page.
+    pub fn unloaded() -> Self {
+        CounterLog {
+            page: 1,
+            next: u32::MAX,
+        }
+    }
+
+    /// Save counters, which are then the latest.
+    pub fn save<F>(&mut self, flash: &mut F, counters: &Counters) -> Result<(), ConfigError>
+    where
+        F: NorFlash,
+    {
+        if self.next.saturating_add(RECORD_LEN as u32) > Self::page_len::<F>() {
+            let full = self.page;
+            self.page = 1 - full;
+            self.next = 0;
+            let offset = Self::offset::<F>(self.page);
+            flash
+                .try_erase(offset, offset + F::ERASE_SIZE as u32)
+                .map_err(|_| ConfigError::Erase)?;
+            Self::write(flash, self.page, self.next, counters)?;
+            self.next += RECORD_LEN as u32;
+            let offset = Self::offset::<F>(full);
+            flash
+                .try_erase(offset, offset + F::ERASE_SIZE as u32)
+                .map_err(|_| ConfigError::Erase)
+        } else {
+            Self::write(flash, self.page, self.next, counters)?;
+            self.next += RECORD_LEN as u32;
+            Ok(())
+        }
+    }
+
+    fn write<F>(flash: &mut F, page: usize, at: u32, counters: &Counters) -> Result<(), ConfigError>
+    where
+        F: NorFlash,
+    {
+        flash
+            .try_write(Self::offset::<F>(page) + at, &counters.to_bytes())
+            .map_err(|_| ConfigError::Write)
+    }
+
+    // Scan a page for its latest record, and where the next is to be written.
+
+    fn scan<F>(flash: &mut F, page: usize) -> Result<(Option<Counters>, u32), ConfigError>
+    where
+        F: NorFlash,
+    {
+        let offset = Self::offset::<F>(page);
+        let page_len = Self::page_len::<F>();
+        let mut block = [0u8; SCAN_RECORDS * RECORD_LEN];
+        let mut latest = None;
+        let mut next = 0;
+        while next < page_len {
+            let len = block.len().min((page_len - next) as usize);
+            flash
+                .try_read(offset + next, &mut block[..len])
+                .map_err(|_| ConfigError::Read)?;
+            let (block_latest, block_next) = find_latest(&block[..len]);
+            latest = block_latest.or(latest);
+            next += block_next as u32;
+            if block_next < len {
+                break;
+            }
+        }
+        Ok((latest, next))
+    }
+
+    fn offset<F>(page: usize) -> u32
+    where
+        F: NorFlash,
+    {
+        (FIRST_PAGE + page as u32) * F::ERASE_SIZE as u32
+    }
+
+    // The length of a page that holds whole records.
+
+    fn page_len<F>() -> u32
+    where
+        F: NorFlash,
+    {
+        (F::ERASE_SIZE - F::ERASE_SIZE % RECORD_LEN) as u32
+    }
+}
+
+/// An uplink frame counter that reserves blocks of counters ahead of their use.
+/// DevNonces are counted likewise.
+///
+/// ```
+/// use app::fcnt::FrameCounter;
+///
+/// // Starting afresh
+/// let mut fcnt = FrameCounter::restore(None, 16);
+/// assert_eq!(fcnt.reserve(), Some(16)); // Persist this before sending
+/// assert_eq!(fcnt.value(), 0);
+/// fcnt.increment();
+/// assert_eq!(fcnt.reserve(), None);
+/// assert_eq!(fcnt.value(), 1);
+/// for _ in 1..16 {
+///     fcnt.increment();
+/// }
+/// assert_eq!(fcnt.reserve(), Some(32));
+/// assert_eq!(fcnt.value(), 16);
+///
+/// // After a reset, where 32 was the last reservation persisted
+/// let mut fcnt = FrameCounter::restore(Some(32), 16);
+/// assert_eq!(fcnt.reserve(), Some(48));
+/// assert_eq!(fcnt.value(), 32);
+/// ```
+pub struct FrameCounter {
+    value: u32,
+    reserved: u32,
+    interval: u32,
+}
+
+impl FrameCounter {
+    /// Resume from the last reservation persisted, if any. Counters are
+    /// reserved `interval` at a time.
+    pub fn restore(reserved: Option<u32>, interval: u32) -> Self {
+        let value = reserved.unwrap_or(0);
+        FrameCounter {
+            value,
+            reserved: value,
+            interval: interval.max(1),
+        }
+    }
+
+    /// The counter to use for the next uplink.
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    /// The last reservation, from which we'd resume.
+    pub fn reserved(&self) -> u32 {
+        self.reserved
+    }
+
+    /// Call before using the counter. If the counter is not covered by the
+    /// current reservation then a new reservation is returned, which must be
+    /// persisted before the counter is used.
+    pub fn reserve(&mut self) -> Option<u32> {
+        if self.value >= self.reserved {
+            self.reserved = self.value.saturating_add(self.interval);
+            Some(self.reserved)
+        } else {
+            None
+        }
+    }
+
+    /// Call once the counter has been used.
+    pub fn increment(&mut self) {
+        self.value = self.value.saturating_add(1);
+    }
+}
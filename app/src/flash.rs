@@ -0,0 +1,108 @@
+//! A flash memory simulated in RAM, so that what we keep in flash may be
+//! tested off the board. As with the nRF9160's flash, erasing sets whole
+//! pages to ones, and writing whole words can only then clear bits.
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+#[derive(Debug, PartialEq)]
+pub enum RamFlashError {
+    OutOfBounds,
+    /// Not of whole words when writing, or of whole pages when erasing.
+    NotAligned,
+}
+
+/// `N` bytes of flash memory, initially erased.
+///
+/// ```
+/// use app::flash::{RamFlash, RamFlashError};
+/// use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+///
+/// let mut flash = RamFlash::<8192>::new();
+/// let mut bytes = [0; 4];
+/// flash.try_read(4096, &mut bytes).unwrap();
+/// assert_eq!(bytes, [0xff; 4]);
+///
+/// // Writing clears bits, but never sets them
+/// flash.try_write(4096, &[0x0f, 0xf0, 0x00, 0xff]).unwrap();
+/// flash.try_write(4096, &[0xff, 0x0f, 0xff, 0x01]).unwrap();
+/// flash.try_read(4096, &mut bytes).unwrap();
+/// assert_eq!(bytes, [0x0f, 0x00, 0x00, 0x01]);
+///
+/// flash.try_erase(4096, 8192).unwrap();
+/// flash.try_read(4096, &mut bytes).unwrap();
+/// assert_eq!(bytes, [0xff; 4]);
+///
+/// assert_eq!(flash.try_write(4097, &[0; 4]), Err(RamFlashError::NotAligned));
+/// assert_eq!(flash.try_write(4096, &[0; 3]), Err(RamFlashError::NotAligned));
+/// assert_eq!(flash.try_erase(0, 100), Err(RamFlashError::NotAligned));
+/// assert_eq!(flash.try_read(8190, &mut bytes), Err(RamFlashError::OutOfBounds));
+/// ```
+pub struct RamFlash<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> RamFlash<N> {
+    pub fn new() -> Self {
+        RamFlash { bytes: [0xff; N] }
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, RamFlashError> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= N => Ok(start..end),
+            _ => Err(RamFlashError::OutOfBounds),
+        }
+    }
+}
+
+impl<const N: usize> Default for RamFlash<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ReadNorFlash for RamFlash<N> {
+    type Error = RamFlashError;
+
+    const READ_SIZE: usize = 1;
+
+    fn try_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.bytes[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> NorFlash for RamFlash<N> {
+    const WRITE_SIZE: usize = 4;
+
+    const ERASE_SIZE: usize = 4096;
+
+    fn try_erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let range = self.range(from, (to as usize).saturating_sub(from as usize))?;
+        if !range.start.is_multiple_of(Self::ERASE_SIZE)
+            || !range.end.is_multiple_of(Self::ERASE_SIZE)
+        {
+            return Err(RamFlashError::NotAligned);
+        }
+        self.bytes[range].iter_mut().for_each(|byte| *byte = 0xff);
+        Ok(())
+    }
+
+    fn try_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        if !range.start.is_multiple_of(Self::WRITE_SIZE)
+            || !range.len().is_multiple_of(Self::WRITE_SIZE)
+        {
+            return Err(RamFlashError::NotAligned);
+        }
+        for (byte, written) in self.bytes[range].iter_mut().zip(bytes) {
+            *byte &= written;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! The Semtech UDP packet forwarder protocol, otherwise known as the Gateway
+//! Messaging Protocol (GWMP). We masquerade as a single channel gateway so that
+//! we can talk directly to a network server's gateway bridge. Uplinks are wrapped
+//! in `PUSH_DATA`, we keep a downlink path open with `PULL_DATA`, and downlinks
+//! arrive to us in `PULL_RESP`.
+//!
+//! Radio metadata is synthetic given that there's no radio involved.
+
+use core::fmt::Write;
+use heapless::{String, Vec};
+
+pub const PROTOCOL_VERSION: u8 = 2;
+
+const PUSH_DATA: u8 = 0x00;
+const PUSH_ACK: u8 = 0x01;
+const PULL_DATA: u8 = 0x02;
+const PULL_RESP: u8 = 0x03;
+const PULL_ACK: u8 = 0x04;
+const TX_ACK: u8 = 0x05;
+
+/// The maximum size of a datagram that we'll create. Large enough to convey
+/// a maximum sized PHYPayload in base64, along with its metadata.
+pub const MAX_DATAGRAM_LEN: usize = 512;
+
+pub type Datagram = Vec<u8, MAX_DATAGRAM_LEN>;
+
+/// The radio metadata to report with each uplink.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RxInfo {
+    pub freq_khz: u32,
+    pub datr: &'static str,
+    pub rssi: i16,
+    pub lsnr: i8,
+}
+
+impl Default for RxInfo {
+    /// The first channel of EU868 at SF7, with a good signal.
+    fn default() -> Self {
+        RxInfo {
+            freq_khz: 868_100,
+            datr: "SF7BW125",
+            rssi: -50,
+            lsnr: 9,
+        }
+    }
+}
+
+fn header(token: u16, identifier: u8) -> Datagram {
+    let mut datagram = Vec::new();
+    let token = token.to_le_bytes();
+    let _ = datagram.extend_from_slice(&[PROTOCOL_VERSION, token[0], token[1], identifier]);
+    datagram
+}
+
+/// Wrap a PHYPayload in a `PUSH_DATA` datagram, as received by us as a gateway
+/// at the given microsecond timestamp. Returns `None` if the payload is too
+/// large to convey.
+///
+/// ```
+/// use app::gwmp::{push_data, RxInfo};
+///
+/// let datagram = push_data(0x0102, 0x0011223344556677, &[0x40, 0x01, 0x02], 1000, &RxInfo::default()).unwrap();
+/// assert_eq!(&datagram[..12], &[0x02, 0x02, 0x01, 0x00, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+/// assert_eq!(
+///     core::str::from_utf8(&datagram[12..]).unwrap(),
+///     "{\"rxpk\":[{\"tmst\":1000,\"chan\":0,\"rfch\":0,\"freq\":868.100,\"stat\":1,\"modu\":\"LORA\",\
+///     \"datr\":\"SF7BW125\",\"codr\":\"4/5\",\"rssi\":-50,\"lsnr\":9,\"size\":3,\"data\":\"QAEC\"}]}"
+/// );
+/// ```
+pub fn push_data(
+    token: u16,
+    gateway_eui: u64,
+    phy_payload: &[u8],
+    tmst: u32,
+    rx_info: &RxInfo,
+) -> Option<Datagram> {
+    let mut datagram = header(token, PUSH_DATA);
+    datagram
+        .extend_from_slice(&gateway_eui.to_be_bytes())
+        .ok()?;
+    let mut json: String<MAX_DATAGRAM_LEN> = String::new();
+    write!(
+        json,
+        "{{\"rxpk\":[{{\"tmst\":{},\"chan\":0,\"rfch\":0,\"freq\":{}.{:03},\"stat\":1,\"modu\":\"LORA\",\
+        \"datr\":\"{}\",\"codr\":\"4/5\",\"rssi\":{},\"lsnr\":{},\"size\":{},\"data\":\"",
+        tmst,
+        rx_info.freq_khz / 1000,
+        rx_info.freq_khz % 1000,
+        rx_info.datr,
+        rx_info.rssi,
+        rx_info.lsnr,
+        phy_payload.len()
+    )
+    .ok()?;
+    base64_encode(phy_payload, &mut json)?;
+    json.push_str("\"}]}").ok()?;
+    datagram.extend_from_slice(json.as_bytes()).ok()?;
+    Some(datagram)
+}
+
+/// A `PULL_DATA` datagram, keeping open the path for downlinks to reach us.
+///
+/// ```
+/// let datagram = app::gwmp::pull_data(0x0102, 0x0011223344556677);
+/// assert_eq!(&datagram[..], &[0x02, 0x02, 0x01, 0x02, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+/// ```
+pub fn pull_data(token: u16, gateway_eui: u64) -> Datagram {
+    let mut datagram = header(token, PULL_DATA);
+    let _ = datagram.extend_from_slice(&gateway_eui.to_be_bytes());
+    datagram
+}
+
+/// A `TX_ACK` datagram, acknowledging a `PULL_RESP` with the same token.
+///
+/// ```
+/// let datagram = app::gwmp::tx_ack(0x0102, 0x0011223344556677);
+/// assert_eq!(&datagram[..], &[0x02, 0x02, 0x01, 0x05, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]);
+/// ```
+pub fn tx_ack(token: u16, gateway_eui: u64) -> Datagram {
+    let mut datagram = header(token, TX_ACK);
+    let _ = datagram.extend_from_slice(&gateway_eui.to_be_bytes());
+    datagram
+}
+
+/// The datagrams that a network server sends to a gateway.
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    PushAck { token: u16 },
+    PullAck { token: u16 },
+    PullResp { token: u16, json: &'a [u8] },
+}
+
+/// Parse a datagram received from the network server.
+///
+/// ```
+/// use app::gwmp::{parse, Packet};
+///
+/// assert_eq!(parse(&[0x02, 0x02, 0x01, 0x01]), Some(Packet::PushAck { token: 0x0102 }));
+/// assert_eq!(parse(&[0x02, 0x02, 0x01, 0x04]), Some(Packet::PullAck { token: 0x0102 }));
+/// assert_eq!(
+///     parse(&[0x02, 0x02, 0x01, 0x03, b'{', b'}']),
+///     Some(Packet::PullResp { token: 0x0102, json: b"{}" })
+/// );
+/// assert_eq!(parse(&[0x01, 0x02, 0x01, 0x01]), None);
+/// assert_eq!(parse(&[0x02, 0x02]), None);
+/// ```
+pub fn parse(bytes: &[u8]) -> Option<Packet<'_>> {
+    if bytes.len() < 4 || bytes[0] != PROTOCOL_VERSION {
+        return None;
+    }
+    let token = u16::from_le_bytes([bytes[1], bytes[2]]);
+    match bytes[3] {
+        PUSH_ACK => Some(Packet::PushAck { token }),
+        PULL_ACK => Some(Packet::PullAck { token }),
+        PULL_RESP => Some(Packet::PullResp {
+            token,
+            json: &bytes[4..],
+        }),
+        _ => None,
+    }
+}
+
+/// Extract and decode the PHYPayload from the `txpk` JSON of a `PULL_RESP`,
+/// returning its length.
+///
+/// ```
+/// let json = br#"{"txpk":{"imme":true,"freq":864.123456,"rfch":0,"powe":14,"modu":"LORA","datr":"SF11BW125","codr":"4/6","ipol":false,"size":32,"data":"H3P3N2i9qc4yt7rK7ldqoeCVJGBybzPY5h1Dd7P7p8v"}}"#;
+/// let mut phy_payload = [0_u8; 256];
+/// assert_eq!(app::gwmp::txpk_data(json, &mut phy_payload), Some(32));
+/// assert_eq!(&phy_payload[..4], &[0x1f, 0x73, 0xf7, 0x37]);
+///
+/// assert_eq!(app::gwmp::txpk_data(br#"{"txpk": {"data" : "QAEC"}}"#, &mut phy_payload), Some(3));
+/// assert_eq!(&phy_payload[..3], &[0x40, 0x01, 0x02]);
+///
+/// assert_eq!(app::gwmp::txpk_data(br#"{"txpk":{}}"#, &mut phy_payload), None);
+/// ```
+pub fn txpk_data(json: &[u8], phy_payload: &mut [u8]) -> Option<usize> {
+    const KEY: &[u8] = b"\"data\"";
+    let mut i = json.windows(KEY.len()).position(|w| w == KEY)? + KEY.len();
+    for expected in b":\"" {
+        while json.get(i)?.is_ascii_whitespace() {
+            i += 1;
+        }
+        if json.get(i)? != expected {
+            return None;
+        }
+        i += 1;
+    }
+    let value = &json[i..];
+    let end = value.iter().position(|b| *b == b'"')?;
+    base64_decode(&value[..end], phy_payload)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding.
+///
+/// ```
+/// use heapless::String;
+///
+/// let mut s: String<16> = String::new();
+/// app::gwmp::base64_encode(b"foobar", &mut s).unwrap();
+/// assert_eq!(s, "Zm9vYmFy");
+///
+/// let mut s: String<16> = String::new();
+/// app::gwmp::base64_encode(b"fooba", &mut s).unwrap();
+/// assert_eq!(s, "Zm9vYmE=");
+///
+/// let mut s: String<16> = String::new();
+/// app::gwmp::base64_encode(b"f", &mut s).unwrap();
+/// assert_eq!(s, "Zg==");
+/// ```
+pub fn base64_encode<const N: usize>(bytes: &[u8], out: &mut String<N>) -> Option<()> {
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            let c = if i <= chunk.len() {
+                BASE64[((n >> (18 - 6 * i)) & 0x3f) as usize] as char
+            } else {
+                '='
+            };
+            out.push(c).ok()?;
+        }
+    }
+    Some(())
+}
+
+/// Decodes standard base64, with or without padding, returning the number
+/// of bytes written.
+///
+/// ```
+/// let mut out = [0_u8; 8];
+/// assert_eq!(app::gwmp::base64_decode(b"Zm9vYmFy", &mut out), Some(6));
+/// assert_eq!(&out[..6], b"foobar");
+/// assert_eq!(app::gwmp::base64_decode(b"Zm9vYmE=", &mut out), Some(5));
+/// assert_eq!(&out[..5], b"fooba");
+/// assert_eq!(app::gwmp::base64_decode(b"Zg", &mut out), Some(1));
+/// assert_eq!(&out[..1], b"f");
+/// assert_eq!(app::gwmp::base64_decode(b"Z!==", &mut out), None);
+/// assert_eq!(app::gwmp::base64_decode(b"Zm9vYmFyYmF6", &mut out), None);
+/// ```
+pub fn base64_decode(text: &[u8], out: &mut [u8]) -> Option<usize> {
+    let text = match text.iter().position(|c| *c == b'=') {
+        Some(p) => &text[..p],
+        None => text,
+    };
+    let mut len = 0;
+    let mut n = 0_u32;
+    let mut bits = 0;
+    for c in text {
+        let v = BASE64.iter().position(|b| b == c)? as u32;
+        n = ((n << 6) | v) & 0x00ff_ffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            *out.get_mut(len)? = (n >> bits) as u8;
+            len += 1;
+        }
+    }
+    Some(len)
+}
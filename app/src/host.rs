@@ -0,0 +1,342 @@
+//! The network server may be addressed by name, in which case we resolve its
+//! address when connecting. Should resolution fail then we fall back to the
+//! last address that we resolved. Once resolved, we keep using the address
+//! until sending to it has failed repeatedly, as the server may have moved.
+//!
+//! Addresses may be IPv4 or IPv6, given that some operators only provide IPv6
+//! PDN contexts. IPv6 addresses are printed as recommended by RFC 5952.
+
+use core::fmt;
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+/// The longest hostname that we hold.
+pub const MAX_HOSTNAME_LEN: usize = 64;
+
+pub type Hostname = String<MAX_HOSTNAME_LEN>;
+
+/// The address of a host.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum IpAddr {
+    V4([u8; 4]),
+    V6([u8; 16]),
+}
+
+impl IpAddr {
+    /// Parse either a dotted quad or an IPv6 address.
+    ///
+    /// ```
+    /// use app::host::IpAddr;
+    ///
+    /// assert_eq!(IpAddr::parse("192.168.1.254"), Some(IpAddr::V4([192, 168, 1, 254])));
+    /// assert_eq!(
+    ///     IpAddr::parse("2001:db8::1"),
+    ///     Some(IpAddr::V6([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]))
+    /// );
+    /// assert_eq!(IpAddr::parse("example.com"), None);
+    /// ```
+    pub fn parse(text: &str) -> Option<Self> {
+        parse_ipv4(text)
+            .map(IpAddr::V4)
+            .or_else(|| parse_ipv6(text).map(IpAddr::V6))
+    }
+}
+
+/// ```
+/// use app::host::IpAddr;
+///
+/// let text = |text| format!("{}", IpAddr::parse(text).unwrap());
+///
+/// assert_eq!(text("192.168.1.254"), "192.168.1.254");
+/// assert_eq!(text("2001:0DB8:0000:0000:0000:0000:0000:0001"), "2001:db8::1");
+/// assert_eq!(text("2001:db8:0:1:1:1:1:1"), "2001:db8:0:1:1:1:1:1"); // A lone zero stays
+/// assert_eq!(text("2001:0:0:1:0:0:0:1"), "2001:0:0:1::1"); // The longest run is shortened
+/// assert_eq!(text("2001:db8:0:0:1:0:0:1"), "2001:db8::1:0:0:1"); // The first of equals
+/// assert_eq!(text("0:0:0:0:0:0:0:0"), "::");
+/// assert_eq!(text("::1"), "::1");
+/// assert_eq!(text("fe80::"), "fe80::");
+/// assert_eq!(text("::ffff:c000:0280"), "::ffff:192.0.2.128");
+/// ```
+impl fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAddr::V4(addr) => write!(f, "{}.{}.{}.{}", addr[0], addr[1], addr[2], addr[3]),
+            IpAddr::V6(addr) => {
+                // IPv4-mapped addresses retain their dotted quad
+                if addr[..10] == [0; 10] && addr[10..12] == [0xff; 2] {
+                    return write!(
+                        f,
+                        "::ffff:{}",
+                        IpAddr::V4([addr[12], addr[13], addr[14], addr[15]])
+                    );
+                }
+
+                let mut groups = [0u16; 8];
+                for (group, octets) in groups.iter_mut().zip(addr.chunks_exact(2)) {
+                    *group = u16::from_be_bytes([octets[0], octets[1]]);
+                }
+
+                // The longest run of two or more zero groups is shortened to
+                // "::", the first of them should there be a tie.
+                let (mut zeros_start, mut zeros_len) = (0, 0);
+                let mut run_start = 0;
+                for (i, group) in groups.iter().enumerate() {
+                    if *group != 0 {
+                        run_start = i + 1;
+                    } else if i + 1 - run_start > zeros_len {
+                        zeros_start = run_start;
+                        zeros_len = i + 1 - run_start;
+                    }
+                }
+
+                let write_groups = |f: &mut fmt::Formatter<'_>, groups: &[u16]| {
+                    for (i, group) in groups.iter().enumerate() {
+                        if i > 0 {
+                            f.write_str(":")?;
+                        }
+                        write!(f, "{:x}", group)?;
+                    }
+                    Ok(())
+                };
+                if zeros_len < 2 {
+                    write_groups(f, &groups)
+                } else {
+                    write_groups(f, &groups[..zeros_start])?;
+                    f.write_str("::")?;
+                    write_groups(f, &groups[zeros_start + zeros_len..])
+                }
+            }
+        }
+    }
+}
+
+/// Parse a dotted quad.
+///
+/// ```
+/// use app::host::parse_ipv4;
+///
+/// assert_eq!(parse_ipv4("192.168.1.254"), Some([192, 168, 1, 254]));
+/// assert_eq!(parse_ipv4("192.168.1"), None);
+/// assert_eq!(parse_ipv4("192.168.1.254.1"), None);
+/// assert_eq!(parse_ipv4("192.168.1.256"), None);
+/// assert_eq!(parse_ipv4("example.com"), None);
+/// ```
+pub fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut octets = text.split('.');
+    let mut addr = [0; 4];
+    for octet in &mut addr {
+        *octet = octets.next()?.parse().ok()?;
+    }
+    if octets.next().is_none() {
+        Some(addr)
+    } else {
+        None
+    }
+}
+
+/// Parse an IPv6 address in any of the text forms of RFC 4291, including
+/// those with a trailing dotted quad.
+///
+/// ```
+/// use app::host::parse_ipv6;
+///
+/// let loopback = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+/// assert_eq!(parse_ipv6("::1"), Some(loopback));
+/// assert_eq!(parse_ipv6("0:0:0:0:0:0:0:1"), Some(loopback));
+/// assert_eq!(parse_ipv6("::"), Some([0; 16]));
+/// assert_eq!(
+///     parse_ipv6("::ffff:192.0.2.128"),
+///     Some([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 128])
+/// );
+/// assert_eq!(parse_ipv6("1:2:3:4:5:6:7"), None);
+/// assert_eq!(parse_ipv6("1:2:3:4:5:6:7:8:9"), None);
+/// assert_eq!(parse_ipv6("1:2:3:4::5:6:7:8"), None);
+/// assert_eq!(parse_ipv6("1::2::3"), None);
+/// assert_eq!(parse_ipv6("1:::2"), None);
+/// assert_eq!(parse_ipv6("12345::"), None);
+/// assert_eq!(parse_ipv6("+1::"), None);
+/// assert_eq!(parse_ipv6("1.2.3.4::"), None);
+/// assert_eq!(parse_ipv6("192.168.1.254"), None);
+/// ```
+pub fn parse_ipv6(text: &str) -> Option<[u8; 16]> {
+    let groups = match text.find("::") {
+        Some(i) => {
+            let (head, head_len) = parse_ipv6_groups(&text[..i], false)?;
+            let (tail, tail_len) = parse_ipv6_groups(&text[i + 2..], true)?;
+            if head_len + tail_len > 7 {
+                return None;
+            }
+            let mut groups = head;
+            groups[8 - tail_len..].copy_from_slice(&tail[..tail_len]);
+            groups
+        }
+        None => match parse_ipv6_groups(text, true)? {
+            (groups, 8) => groups,
+            _ => return None,
+        },
+    };
+    let mut addr = [0; 16];
+    for (octets, group) in addr.chunks_exact_mut(2).zip(groups.iter()) {
+        octets.copy_from_slice(&group.to_be_bytes());
+    }
+    Some(addr)
+}
+
+// Parse colon separated groups, the last of which may be a dotted quad,
+// returning them along with their number.
+
+fn parse_ipv6_groups(text: &str, dotted_quad_last: bool) -> Option<([u16; 8], usize)> {
+    let mut groups = [0; 8];
+    let mut len = 0;
+    if text.is_empty() {
+        return Some((groups, len));
+    }
+    let mut parts = text.split(':').peekable();
+    while let Some(part) = parts.next() {
+        if dotted_quad_last && parts.peek().is_none() && part.contains('.') {
+            let [a, b, c, d] = parse_ipv4(part)?;
+            *groups.get_mut(len)? = u16::from_be_bytes([a, b]);
+            *groups.get_mut(len + 1)? = u16::from_be_bytes([c, d]);
+            len += 2;
+        } else {
+            if part.is_empty() || part.len() > 4 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            *groups.get_mut(len)? = u16::from_str_radix(part, 16).ok()?;
+            len += 1;
+        }
+    }
+    Some((groups, len))
+}
+
+/// Parse a hostname as per RFC 1123: dot separated labels of letters, digits
+/// and hyphens, with labels neither starting nor ending with a hyphen.
+///
+/// ```
+/// use app::host::parse_hostname;
+///
+/// assert_eq!(parse_hostname("ns1.example.com").unwrap(), "ns1.example.com");
+/// assert_eq!(parse_hostname("localhost").unwrap(), "localhost");
+/// assert!(parse_hostname("").is_none());
+/// assert!(parse_hostname("-example.com").is_none());
+/// assert!(parse_hostname("example..com").is_none());
+/// assert!(parse_hostname("exa_mple.com").is_none());
+/// assert!(parse_hostname(core::str::from_utf8(&[b'a'; 65]).unwrap()).is_none());
+/// ```
+pub fn parse_hostname(text: &str) -> Option<Hostname> {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if text.split('.').all(valid_label) {
+        let mut hostname = Hostname::new();
+        hostname.push_str(text).ok()?;
+        Some(hostname)
+    } else {
+        None
+    }
+}
+
+/// Decides when to resolve the network server's address, and remembers the
+/// last address resolved.
+///
+/// ```
+/// use app::host::{IpAddr, Resolver};
+///
+/// let addr = |b| Some(IpAddr::V4([10, 0, 0, b]));
+///
+/// let mut resolver = Resolver::new(2);
+/// assert_eq!(resolver.address(|| None), None); // Nothing to fall back on
+/// assert_eq!(resolver.address(|| addr(1)), addr(1));
+///
+/// // We keep using the address...
+/// assert_eq!(resolver.address(|| panic!("Resolved needlessly")), addr(1));
+/// resolver.link_failed();
+/// assert_eq!(resolver.address(|| panic!("Resolved needlessly")), addr(1));
+///
+/// // ...until sending to it repeatedly fails
+/// resolver.link_failed();
+/// assert_eq!(resolver.address(|| addr(2)), addr(2));
+///
+/// // Falling back to the last address resolved if we can't resolve
+/// resolver.link_failed();
+/// resolver.link_failed();
+/// assert_eq!(resolver.address(|| None), addr(2));
+/// assert_eq!(resolver.address(|| addr(3)), addr(3));
+///
+/// // Sending resets the count of failures
+/// resolver.link_failed();
+/// resolver.sent();
+/// resolver.link_failed();
+/// assert_eq!(resolver.address(|| panic!("Resolved needlessly")), addr(3));
+///
+/// // After a reset, resolving again, but falling back to the address last
+/// // resolved before it
+/// let mut resolver = Resolver::with_last(2, addr(3));
+/// assert_eq!(resolver.address(|| None), addr(3));
+/// assert_eq!(resolver.address(|| addr(4)), addr(4));
+/// assert_eq!(resolver.last(), addr(4));
+/// ```
+pub struct Resolver {
+    cached: Option<IpAddr>,
+    resolve_after: u8,
+    failures: u8,
+    stale: bool,
+}
+
+impl Resolver {
+    /// Resolve again once sending has failed this many times in a row.
+    pub fn new(resolve_after: u8) -> Self {
+        Resolver {
+            cached: None,
+            resolve_after: resolve_after.max(1),
+            failures: 0,
+            stale: false,
+        }
+    }
+
+    /// As `new`, along with the address last resolved before a reset, which we
+    /// fall back on until we've resolved afresh.
+    pub fn with_last(resolve_after: u8, last: Option<IpAddr>) -> Self {
+        Resolver {
+            cached: last,
+            stale: last.is_some(),
+            ..Self::new(resolve_after)
+        }
+    }
+
+    /// The address to connect to, resolving it if we have to.
+    pub fn address<F>(&mut self, resolve: F) -> Option<IpAddr>
+    where
+        F: FnOnce() -> Option<IpAddr>,
+    {
+        if self.cached.is_none() || self.stale {
+            if let Some(addr) = resolve() {
+                self.cached = Some(addr);
+                self.stale = false;
+                self.failures = 0;
+            }
+        }
+        self.cached
+    }
+
+    pub fn link_failed(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.resolve_after {
+            self.stale = true;
+        }
+    }
+
+    pub fn sent(&mut self) {
+        self.failures = 0;
+        self.stale = false;
+    }
+
+    /// The address last resolved, if any.
+    pub fn last(&self) -> Option<IpAddr> {
+        self.cached
+    }
+}
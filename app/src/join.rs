@@ -0,0 +1,166 @@
+//! Over-the-air activation (OTAA) as per LoRaWAN 1.0.4. We send a JoinRequest
+//! with a DevNonce that must never repeat, so the device is expected to persist
+//! it. A JoinAccept provides our DevAddr and the material from which our session
+//! keys are derived.
+
+use heapless::Vec;
+use lorawan_encoding::{
+    creator::JoinRequestCreator,
+    keys,
+    parser::{parse, DevNonce, JoinAcceptPayload, PhyPayload},
+};
+
+use crate::Frame;
+
+/// The delay between sending a JoinRequest and the first receive window.
+pub const JOIN_ACCEPT_DELAY1_S: u8 = 5;
+
+/// The keys and address of a LoRaWAN session, whether activated by
+/// personalisation or over the air.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Session {
+    pub dev_addr: u32,
+    pub nwk_skey: u128,
+    pub app_skey: u128,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JoinError {
+    Malformed,
+    InvalidMic,
+}
+
+/// Return a JoinRequest for the given EUIs and DevNonce, signed with our AppKey.
+///
+/// ```
+/// let bytes = app::join::join_request(
+///     0x0004A30B001C0530,
+///     0x70B3D57ED0000001,
+///     0x0102,
+///     0x2B7E151628AED2A6ABF7158809CF4F3C,
+/// );
+/// assert_eq!(
+///     &bytes[..],
+///     &[
+///         0x00, 0x01, 0x00, 0x00, 0xd0, 0x7e, 0xd5, 0xb3, 0x70, 0x30, 0x05, 0x1c, 0x00, 0x0b,
+///         0xa3, 0x04, 0x00, 0x02, 0x01, 0x40, 0x94, 0x49, 0x37
+///     ]
+/// );
+/// ```
+pub fn join_request(dev_eui: u64, join_eui: u64, dev_nonce: u16, app_key: u128) -> Frame {
+    let mut phy = JoinRequestCreator::new();
+    phy.set_app_eui(&join_eui.to_le_bytes())
+        .set_dev_eui(&dev_eui.to_le_bytes())
+        .set_dev_nonce(&dev_nonce.to_le_bytes());
+    let bytes_ref = phy.build(&keys::AES128(app_key.to_le_bytes())).unwrap();
+    Vec::from_slice(bytes_ref).unwrap()
+}
+
+/// Decrypt and validate a JoinAccept received in response to a JoinRequest with
+/// the given DevNonce, deriving the session it establishes. Note that the bytes
+/// are decrypted in place.
+///
+/// ```
+/// use app::join::{decode_join_accept, JoinError, Session};
+///
+/// let app_key = 0x2B7E151628AED2A6ABF7158809CF4F3C;
+/// let frame = [
+///     0x20, 0x74, 0x3b, 0x74, 0x20, 0xe7, 0x52, 0x0f, 0x66, 0xb7, 0x64, 0x75, 0x60, 0x90, 0xb9,
+///     0xc2, 0x9c,
+/// ];
+///
+/// let mut bytes = frame;
+/// assert_eq!(
+///     decode_join_accept(&mut bytes, 0x0102, app_key),
+///     Ok(Session {
+///         dev_addr: 0x26011234,
+///         nwk_skey: 0xC31E53FC2D3B3F150F2499D8EBF2C140,
+///         app_skey: 0x214C5FE5B951AED43374F7A06F28BA68,
+///     })
+/// );
+///
+/// let mut bytes = frame;
+/// assert_eq!(
+///     decode_join_accept(&mut bytes, 0x0102, 0),
+///     Err(JoinError::InvalidMic)
+/// );
+///
+/// assert_eq!(
+///     decode_join_accept(&mut [0x20, 0x00], 0x0102, app_key),
+///     Err(JoinError::Malformed)
+/// );
+/// ```
+pub fn decode_join_accept(
+    bytes: &mut [u8],
+    dev_nonce: u16,
+    app_key: u128,
+) -> Result<Session, JoinError> {
+    let app_key = keys::AES128(app_key.to_le_bytes());
+    let join_accept = match parse(bytes) {
+        Ok(PhyPayload::JoinAccept(JoinAcceptPayload::Encrypted(join_accept))) => {
+            join_accept.decrypt(&app_key)
+        }
+        _ => return Err(JoinError::Malformed),
+    };
+    if !join_accept.validate_mic(&app_key) {
+        return Err(JoinError::InvalidMic);
+    }
+
+    let dev_nonce_bytes = dev_nonce.to_le_bytes();
+    let dev_nonce = DevNonce::from(&dev_nonce_bytes);
+    let mut dev_addr = [0; 4];
+    dev_addr.copy_from_slice(join_accept.dev_addr().as_ref());
+
+    Ok(Session {
+        dev_addr: u32::from_le_bytes(dev_addr),
+        nwk_skey: u128::from_le_bytes(join_accept.derive_newskey(&dev_nonce, &app_key).0),
+        app_skey: u128::from_le_bytes(join_accept.derive_appskey(&dev_nonce, &app_key).0),
+    })
+}
+
+/// Decides when to re-join given whether our uplinks were heard, being
+/// confirmed uplinks that were acknowledged, or link checks that were answered.
+/// We re-join once the configured number of consecutive uplinks has gone
+/// unheard. Zero disables re-joining.
+///
+/// ```
+/// use app::join::Rejoin;
+///
+/// let mut rejoin = Rejoin::new(2);
+/// assert!(!rejoin.uplink(false));
+/// assert!(!rejoin.uplink(true));
+/// assert!(!rejoin.uplink(false));
+/// assert!(rejoin.uplink(false));
+/// assert!(!rejoin.uplink(false)); // Starts counting again
+///
+/// let mut never = Rejoin::new(0);
+/// for _ in 0..255 {
+///     assert!(!never.uplink(false));
+/// }
+/// ```
+pub struct Rejoin {
+    after: u8,
+    failures: u8,
+}
+
+impl Rejoin {
+    pub fn new(after: u8) -> Self {
+        Rejoin { after, failures: 0 }
+    }
+
+    /// Record whether an uplink was heard, returning true if it is time to
+    /// re-join.
+    pub fn uplink(&mut self, heard: bool) -> bool {
+        if heard || self.after == 0 {
+            self.failures = 0;
+            return false;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.after {
+            self.failures = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -1,47 +1,124 @@
 #![cfg_attr(not(test), no_std)]
 
-use lorawan_encoding::keys;
+use heapless::Vec;
+use join::Session;
+use lorawan_encoding::{keys, maccommands::SerializableMacCommand};
+use mac::{FOpt, UplinkMacCommand, MAX_FOPTS_LEN};
 
-/// Return a LoRaWAN data-up-confirmed payload We'll lay the packet out
-// as follows, and using an FPort of 1:
-///
-/// Start |   End | Description
-///     0 |     1 | Temperature (C) * 100
-///     2 |     5 | Pressure (hPA) * 100
-///     6 |     9 | Humidity (%) * 1000
-///    10 |    13 | Gas Resistence
+pub mod batch;
+pub mod battery;
+pub mod codec;
+pub mod config;
+pub mod connectivity;
+pub mod crash;
+pub mod diagnostics;
+pub mod downlink;
+pub mod fcnt;
+pub mod flash;
+pub mod gwmp;
+pub mod host;
+pub mod join;
+pub mod lpp;
+pub mod mac;
+pub mod power;
+pub mod queue;
+pub mod reset;
+pub mod retransmission;
+pub mod system_mode;
+
+/// The maximum size of a PHYPayload that we'll create.
+pub const MAX_FRAME_LEN: usize = 256;
+
+/// A PHYPayload ready for transmission.
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+/// The FPort on which single readings are sent.
+pub const READING_F_PORT: u8 = 1;
+
+/// Return a LoRaWAN data-up-unconfirmed payload for our session, conveying an
+/// application payload on the given FPort along with any MAC commands in
+/// FOpts. Application payloads are as encoded by a `codec::PayloadCodec` and
+/// are at most `codec::MAX_PAYLOAD_LEN` bytes, which always fits.
 ///
 /// ```
-/// use app::EnvironmentalPayload;
-/// let bytes = app::data_up_unconfirmed(0, 0, &EnvironmentalPayload { temperature: 0, pressure: 1, humidity: 2, gas_resistance: 3 }, 0_u128, 0_u128);
+/// use app::join::Session;
+/// use app::mac::UplinkMacCommand;
+/// let session = Session { dev_addr: 0, nwk_skey: 0, app_skey: 0 };
+/// let bytes = app::data_up_unconfirmed(&session, 0, 1, &[0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3], &[]);
+/// assert_eq!(bytes[0], 0x40);
+/// assert_eq!(bytes[8], 0x01); // FPort
+/// assert_eq!(bytes.len(), 27);
+/// let bytes = app::data_up_unconfirmed(&session, 0, 1, &[0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3], &[UplinkMacCommand::DevStatusAns { battery: 255, margin: 0 }]);
+/// assert_eq!(bytes[5], 0x03); // FOptsLen
+/// assert_eq!(&bytes[8..11], &[0x06, 0xff, 0x00]);
+/// assert_eq!(bytes.len(), 30);
+/// let bytes = app::data_up_unconfirmed(&session, 0, 2, &[0x01], &[]);
+/// assert_eq!(bytes[8], 0x02); // FPort
+/// assert_eq!(bytes.len(), 14);
 /// ```
 pub fn data_up_unconfirmed(
-    dev_addr: u32,
+    session: &Session,
+    fcnt: u32,
+    f_port: u8,
+    payload: &[u8],
+    fopts: &[UplinkMacCommand],
+) -> Frame {
+    data_up(false, session, fcnt, f_port, payload, fopts)
+}
+
+/// Return a LoRaWAN data-up-confirmed payload, laid out as per
+/// `data_up_unconfirmed`. The network server is expected to acknowledge
+/// the frame by setting the ACK bit of its next downlink. Retransmissions
+/// of a confirmed frame must use the same frame counter.
+///
+/// ```
+/// use app::join::Session;
+/// let session = Session { dev_addr: 0, nwk_skey: 0, app_skey: 0 };
+/// let bytes = app::data_up_confirmed(&session, 0, 1, &[0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3], &[]);
+/// assert_eq!(bytes[0], 0x80);
+/// ```
+pub fn data_up_confirmed(
+    session: &Session,
+    fcnt: u32,
+    f_port: u8,
+    payload: &[u8],
+    fopts: &[UplinkMacCommand],
+) -> Frame {
+    data_up(true, session, fcnt, f_port, payload, fopts)
+}
+
+fn data_up(
+    confirmed: bool,
+    session: &Session,
     fcnt: u32,
-    payload: &EnvironmentalPayload,
-    nwk_skey: u128,
-    app_skey: u128,
-) -> [u8; 27] {
+    f_port: u8,
+    payload: &[u8],
+    fopts: &[UplinkMacCommand],
+) -> Frame {
     let mut phy = lorawan_encoding::creator::DataPayloadCreator::new();
-    phy.set_confirmed(false)
+    phy.set_confirmed(confirmed)
         .set_uplink(true)
-        .set_f_port(1)
-        .set_dev_addr(&dev_addr.to_le_bytes())
+        .set_f_port(f_port)
+        .set_dev_addr(&session.dev_addr.to_le_bytes())
         .set_fcnt(fcnt);
+    let fopts: Vec<FOpt, MAX_FOPTS_LEN> = fopts.iter().map(FOpt::from).collect();
+    let cmds: Vec<&dyn SerializableMacCommand, MAX_FOPTS_LEN> = fopts
+        .iter()
+        .map(|c| c as &dyn SerializableMacCommand)
+        .collect();
     let bytes_ref = phy
         .build(
-            &payload.to_be_bytes(),
-            &[],
-            &keys::AES128(nwk_skey.to_le_bytes()),
-            &keys::AES128(app_skey.to_le_bytes()),
+            payload,
+            &cmds,
+            &keys::AES128(session.nwk_skey.to_le_bytes()),
+            &keys::AES128(session.app_skey.to_le_bytes()),
         )
         .unwrap();
-    let mut bytes = [0_u8; 27];
-    bytes.copy_from_slice(bytes_ref);
-    bytes
+    Vec::from_slice(bytes_ref).unwrap()
 }
 
-/// The payload to convey over LoRaWAN
+/// Readings from the environmental sensor, in the units that
+/// `codec::EnvironmentalCodec` conveys.
 pub struct EnvironmentalPayload {
     pub temperature: i16,
     pub pressure: u32,
@@ -0,0 +1,244 @@
+//! Cayenne Low Power Payload (LPP). Each value is preceded by a channel, which
+//! distinguishes sensors of the same type, and a data type, which determines
+//! the value's size and resolution. Values are big endian. Dashboards that
+//! understand LPP can then decode our uplinks without a hand-written decoder.
+
+use crate::{
+    battery::BatteryVoltage,
+    codec::{EncodeError, Payload, PayloadCodec},
+    EnvironmentalPayload,
+};
+
+pub const ANALOG_INPUT: u8 = 2;
+pub const TEMPERATURE: u8 = 103;
+pub const HUMIDITY: u8 = 104;
+pub const ACCELEROMETER: u8 = 113;
+pub const BAROMETER: u8 = 115;
+pub const VOLTAGE: u8 = 116;
+pub const GPS: u8 = 136;
+
+fn add(payload: &mut Payload, channel: u8, data_type: u8, data: &[u8]) -> Result<(), EncodeError> {
+    if payload.capacity() - payload.len() < 2 + data.len() {
+        return Err(EncodeError::Overflow);
+    }
+    let _ = payload.extend_from_slice(&[channel, data_type]);
+    let _ = payload.extend_from_slice(data);
+    Ok(())
+}
+
+fn i24_be_bytes(value: i32) -> [u8; 3] {
+    let bytes = value.to_be_bytes();
+    [bytes[1], bytes[2], bytes[3]]
+}
+
+/// An analog input, in hundredths.
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::analog_input(&mut payload, 2, -1).unwrap();
+/// assert_eq!(&payload[..], &[0x02, 0x02, 0xff, 0xff]);
+/// ```
+pub fn analog_input(
+    payload: &mut Payload,
+    channel: u8,
+    hundredths: i16,
+) -> Result<(), EncodeError> {
+    add(payload, channel, ANALOG_INPUT, &hundredths.to_be_bytes())
+}
+
+/// A temperature, in tenths of a degree Celsius. From the LPP examples:
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::temperature(&mut payload, 3, 272).unwrap();
+/// app::lpp::temperature(&mut payload, 5, 255).unwrap();
+/// assert_eq!(&payload[..], &[0x03, 0x67, 0x01, 0x10, 0x05, 0x67, 0x00, 0xff]);
+///
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::temperature(&mut payload, 1, -41).unwrap();
+/// assert_eq!(&payload[..], &[0x01, 0x67, 0xff, 0xd7]);
+/// ```
+pub fn temperature(payload: &mut Payload, channel: u8, tenths_c: i16) -> Result<(), EncodeError> {
+    add(payload, channel, TEMPERATURE, &tenths_c.to_be_bytes())
+}
+
+/// A relative humidity, in half percents.
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::humidity(&mut payload, 2, 97).unwrap(); // 48.5%
+/// assert_eq!(&payload[..], &[0x02, 0x68, 0x61]);
+/// ```
+pub fn humidity(payload: &mut Payload, channel: u8, half_percent: u8) -> Result<(), EncodeError> {
+    add(payload, channel, HUMIDITY, &[half_percent])
+}
+
+/// An acceleration along each axis, in thousandths of G. From the LPP examples:
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::accelerometer(&mut payload, 6, 1234, -1234, 0).unwrap();
+/// assert_eq!(&payload[..], &[0x06, 0x71, 0x04, 0xd2, 0xfb, 0x2e, 0x00, 0x00]);
+/// ```
+pub fn accelerometer(
+    payload: &mut Payload,
+    channel: u8,
+    x_milli_g: i16,
+    y_milli_g: i16,
+    z_milli_g: i16,
+) -> Result<(), EncodeError> {
+    let (x, y, z) = (
+        x_milli_g.to_be_bytes(),
+        y_milli_g.to_be_bytes(),
+        z_milli_g.to_be_bytes(),
+    );
+    add(
+        payload,
+        channel,
+        ACCELEROMETER,
+        &[x[0], x[1], y[0], y[1], z[0], z[1]],
+    )
+}
+
+/// A barometric pressure, in tenths of a hPa.
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::barometer(&mut payload, 3, 10132).unwrap(); // 1013.2 hPa
+/// assert_eq!(&payload[..], &[0x03, 0x73, 0x27, 0x94]);
+/// ```
+pub fn barometer(payload: &mut Payload, channel: u8, tenths_hpa: u16) -> Result<(), EncodeError> {
+    add(payload, channel, BAROMETER, &tenths_hpa.to_be_bytes())
+}
+
+/// A voltage, in hundredths of a volt. Not one of the original LPP types, but
+/// commonly supported as an extension.
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::voltage(&mut payload, 7, 372).unwrap(); // 3.72 V
+/// assert_eq!(&payload[..], &[0x07, 0x74, 0x01, 0x74]);
+/// ```
+pub fn voltage(payload: &mut Payload, channel: u8, hundredths_v: u16) -> Result<(), EncodeError> {
+    add(payload, channel, VOLTAGE, &hundredths_v.to_be_bytes())
+}
+
+/// A location, with latitude and longitude in ten thousandths of a degree,
+/// and altitude in centimetres. Each is a signed 24 bit value. From the LPP
+/// examples:
+///
+/// ```
+/// let mut payload = app::codec::Payload::new();
+/// app::lpp::gps(&mut payload, 1, 423519, -879094, 1000).unwrap();
+/// assert_eq!(&payload[..], &[0x01, 0x88, 0x06, 0x76, 0x5f, 0xf2, 0x96, 0x0a, 0x00, 0x03, 0xe8]);
+/// ```
+pub fn gps(
+    payload: &mut Payload,
+    channel: u8,
+    latitude: i32,
+    longitude: i32,
+    altitude_cm: i32,
+) -> Result<(), EncodeError> {
+    let (lat, lon, alt) = (
+        i24_be_bytes(latitude),
+        i24_be_bytes(longitude),
+        i24_be_bytes(altitude_cm),
+    );
+    add(
+        payload,
+        channel,
+        GPS,
+        &[
+            lat[0], lat[1], lat[2], lon[0], lon[1], lon[2], alt[0], alt[1], alt[2],
+        ],
+    )
+}
+
+pub const TEMPERATURE_CHANNEL: u8 = 1;
+pub const HUMIDITY_CHANNEL: u8 = 2;
+pub const BAROMETER_CHANNEL: u8 = 3;
+pub const GAS_RESISTANCE_CHANNEL: u8 = 4;
+pub const BATTERY_CHANNEL: u8 = 5;
+
+/// Environmental readings as LPP. Gas resistance has no LPP type, so it is
+/// conveyed as an analog input in kilohms, saturating at 327.67 kΩ. Readings
+/// are rounded down to LPP's resolution.
+///
+/// ```
+/// use app::codec::{Payload, PayloadCodec};
+/// use app::lpp::LppCodec;
+/// use app::EnvironmentalPayload;
+///
+/// let mut payload = Payload::new();
+/// LppCodec
+///     .encode(
+///         &EnvironmentalPayload { temperature: 2721, pressure: 101325, humidity: 48500, gas_resistance: 12345 },
+///         &mut payload,
+///     )
+///     .unwrap();
+/// assert_eq!(
+///     &payload[..],
+///     &[
+///         0x01, 0x67, 0x01, 0x10, // 27.2 C
+///         0x02, 0x68, 0x61, // 48.5 %
+///         0x03, 0x73, 0x27, 0x94, // 1013.2 hPa
+///         0x04, 0x02, 0x04, 0xd2, // 12.34 kΩ
+///     ]
+/// );
+///
+/// let mut payload = Payload::new();
+/// LppCodec
+///     .encode(
+///         &EnvironmentalPayload { temperature: -2721, pressure: 0, humidity: 0, gas_resistance: 0 },
+///         &mut payload,
+///     )
+///     .unwrap();
+/// assert_eq!(&payload[..4], &[0x01, 0x67, 0xfe, 0xef]); // -27.3 C
+/// ```
+pub struct LppCodec;
+
+impl PayloadCodec<EnvironmentalPayload> for LppCodec {
+    fn encode(
+        &self,
+        value: &EnvironmentalPayload,
+        payload: &mut Payload,
+    ) -> Result<(), EncodeError> {
+        let len = payload.len();
+        let result = encode_environmental(value, payload);
+        if result.is_err() {
+            payload.truncate(len);
+        }
+        result
+    }
+}
+
+fn encode_environmental(
+    value: &EnvironmentalPayload,
+    payload: &mut Payload,
+) -> Result<(), EncodeError> {
+    let tenths_c = value.temperature.div_euclid(10);
+    temperature(payload, TEMPERATURE_CHANNEL, tenths_c)?;
+    let half_percent = (value.humidity / 500).min(u8::MAX as u32) as u8;
+    humidity(payload, HUMIDITY_CHANNEL, half_percent)?;
+    let tenths_hpa = (value.pressure / 10).min(u16::MAX as u32) as u16;
+    barometer(payload, BAROMETER_CHANNEL, tenths_hpa)?;
+    let hundredths_kohm = (value.gas_resistance / 10).min(i16::MAX as u32) as i16;
+    analog_input(payload, GAS_RESISTANCE_CHANNEL, hundredths_kohm)
+}
+
+/// Our battery voltage, rounded down to hundredths of a volt.
+///
+/// ```
+/// use app::battery::BatteryVoltage;
+/// use app::codec::{Payload, PayloadCodec};
+/// use app::lpp::LppCodec;
+///
+/// let mut payload = Payload::new();
+/// LppCodec.encode(&BatteryVoltage { mv: 3712 }, &mut payload).unwrap();
+/// assert_eq!(&payload[..], &[0x05, 0x74, 0x01, 0x73]); // 3.71 V
+/// ```
+impl PayloadCodec<BatteryVoltage> for LppCodec {
+    fn encode(&self, value: &BatteryVoltage, payload: &mut Payload) -> Result<(), EncodeError> {
+        voltage(payload, BATTERY_CHANNEL, value.mv / 10)
+    }
+}
@@ -0,0 +1,399 @@
+//! LoRaWAN MAC commands as per the LoRaWAN 1.0.4 specification. Commands
+//! arrive from the network server in a downlink's FOpts, or in its FRMPayload
+//! when using FPort 0. We answer them by piggybacking in the FOpts of our next
+//! uplink. We're not a radio, so commands relating to channels, data rates
+//! and the like are recognised but not answered.
+
+use heapless::Vec;
+use lorawan_encoding::maccommands::SerializableMacCommand;
+
+use crate::downlink::Downlink;
+
+/// The maximum number of bytes that can be conveyed by FOpts.
+pub const MAX_FOPTS_LEN: usize = 15;
+
+/// Signifies that we're unable to measure our battery level.
+pub const BATTERY_UNKNOWN: u8 = 255;
+
+const LINK_CHECK: u8 = 0x02;
+const LINK_ADR: u8 = 0x03;
+const DUTY_CYCLE: u8 = 0x04;
+const RX_PARAM_SETUP: u8 = 0x05;
+const DEV_STATUS: u8 = 0x06;
+const NEW_CHANNEL: u8 = 0x07;
+const RX_TIMING_SETUP: u8 = 0x08;
+const TX_PARAM_SETUP: u8 = 0x09;
+const DL_CHANNEL: u8 = 0x0a;
+const DEVICE_TIME: u8 = 0x0d;
+
+/// Commands sent to us by the network server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DownlinkMacCommand {
+    LinkCheckAns {
+        margin: u8,
+        gw_cnt: u8,
+    },
+    DutyCycleReq {
+        max_duty_cycle: u8,
+    },
+    DevStatusReq,
+    RxTimingSetupReq {
+        delay: u8,
+    },
+    /// A command we know the length of and so can skip, but don't act on.
+    Unsupported {
+        cid: u8,
+    },
+}
+
+/// Commands that we send to the network server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UplinkMacCommand {
+    LinkCheckReq,
+    DutyCycleAns,
+    DevStatusAns { battery: u8, margin: i8 },
+    RxTimingSetupAns,
+}
+
+impl UplinkMacCommand {
+    /// The command as bytes, including its CID.
+    ///
+    /// ```
+    /// use app::mac::UplinkMacCommand;
+    ///
+    /// assert_eq!(&UplinkMacCommand::LinkCheckReq.to_bytes(), &[0x02]);
+    /// assert_eq!(&UplinkMacCommand::DutyCycleAns.to_bytes(), &[0x04]);
+    /// assert_eq!(
+    ///     &UplinkMacCommand::DevStatusAns { battery: 254, margin: -2 }.to_bytes(),
+    ///     &[0x06, 0xfe, 0x3e]
+    /// );
+    /// assert_eq!(
+    ///     &UplinkMacCommand::DevStatusAns { battery: 0, margin: 31 }.to_bytes(),
+    ///     &[0x06, 0x00, 0x1f]
+    /// );
+    /// assert_eq!(&UplinkMacCommand::RxTimingSetupAns.to_bytes(), &[0x08]);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8, 3> {
+        let bytes: &[u8] = match *self {
+            UplinkMacCommand::LinkCheckReq => &[LINK_CHECK],
+            UplinkMacCommand::DutyCycleAns => &[DUTY_CYCLE],
+            UplinkMacCommand::DevStatusAns { battery, margin } => {
+                &[DEV_STATUS, battery, (margin.clamp(-32, 31) as u8) & 0x3f]
+            }
+            UplinkMacCommand::RxTimingSetupAns => &[RX_TIMING_SETUP],
+        };
+        Vec::from_slice(bytes).unwrap()
+    }
+
+    /// Sticky answers must be sent in every uplink until a downlink is received.
+    fn is_sticky(&self) -> bool {
+        matches!(self, UplinkMacCommand::RxTimingSetupAns)
+    }
+}
+
+/// Iterates over the commands conveyed by a downlink. Iteration stops at the
+/// first command that we don't recognise or that is truncated, as we cannot
+/// know where any following command would start.
+///
+/// ```
+/// use app::mac::{parse_downlink_commands, DownlinkMacCommand};
+///
+/// let mut commands = parse_downlink_commands(&[
+///     0x02, 0x14, 0x03,
+///     0x03, 0x50, 0xff, 0x00, 0x01,
+///     0x04, 0x02,
+///     0x06,
+///     0x08, 0x05,
+///     0x0d, 0x01, 0x02, 0x03, 0x04, 0x80,
+///     0xff, 0x06,
+/// ]);
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::LinkCheckAns { margin: 20, gw_cnt: 3 }));
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::Unsupported { cid: 0x03 }));
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::DutyCycleReq { max_duty_cycle: 2 }));
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::DevStatusReq));
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::RxTimingSetupReq { delay: 5 }));
+/// assert_eq!(commands.next(), Some(DownlinkMacCommand::Unsupported { cid: 0x0d }));
+/// assert_eq!(commands.next(), None);
+///
+/// let mut commands = parse_downlink_commands(&[0x02, 0x14]);
+/// assert_eq!(commands.next(), None);
+/// ```
+pub fn parse_downlink_commands(bytes: &[u8]) -> DownlinkMacCommands<'_> {
+    DownlinkMacCommands { bytes }
+}
+
+pub struct DownlinkMacCommands<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for DownlinkMacCommands<'a> {
+    type Item = DownlinkMacCommand;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (cid, rest) = self.bytes.split_first()?;
+        let len = match *cid {
+            LINK_CHECK => 2,
+            LINK_ADR => 4,
+            DUTY_CYCLE => 1,
+            RX_PARAM_SETUP => 4,
+            DEV_STATUS => 0,
+            NEW_CHANNEL => 5,
+            RX_TIMING_SETUP => 1,
+            TX_PARAM_SETUP => 1,
+            DL_CHANNEL => 4,
+            DEVICE_TIME => 5,
+            _ => {
+                self.bytes = &[];
+                return None;
+            }
+        };
+        if rest.len() < len {
+            self.bytes = &[];
+            return None;
+        }
+        let (payload, rest) = rest.split_at(len);
+        self.bytes = rest;
+        Some(match *cid {
+            LINK_CHECK => DownlinkMacCommand::LinkCheckAns {
+                margin: payload[0],
+                gw_cnt: payload[1],
+            },
+            DUTY_CYCLE => DownlinkMacCommand::DutyCycleReq {
+                max_duty_cycle: payload[0] & 0x0f,
+            },
+            DEV_STATUS => DownlinkMacCommand::DevStatusReq,
+            RX_TIMING_SETUP => DownlinkMacCommand::RxTimingSetupReq {
+                delay: payload[0] & 0x0f,
+            },
+            cid => DownlinkMacCommand::Unsupported { cid },
+        })
+    }
+}
+
+/// The result of our last LinkCheckReq.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkCheck {
+    pub margin: u8,
+    pub gw_cnt: u8,
+}
+
+/// Processes the commands from the network server, collecting the answers to
+/// be sent with the next uplink, along with any requests of our own.
+///
+/// ```
+/// use app::mac::{MacCommands, UplinkMacCommand};
+///
+/// let mut mac = MacCommands::new();
+/// mac.set_battery(128);
+/// mac.handle(&[0x06, 0x04, 0x03, 0x08, 0x02]);
+/// assert_eq!(
+///     mac.uplink_commands(),
+///     &[
+///         UplinkMacCommand::DevStatusAns { battery: 128, margin: 0 },
+///         UplinkMacCommand::DutyCycleAns,
+///         UplinkMacCommand::RxTimingSetupAns,
+///     ]
+/// );
+/// assert_eq!(mac.max_duty_cycle(), 3);
+/// assert_eq!(mac.rx_delay_s(), 2);
+///
+/// // RXTimingSetupAns is repeated until we receive a downlink.
+/// mac.uplink_sent();
+/// assert_eq!(mac.uplink_commands(), &[UplinkMacCommand::RxTimingSetupAns]);
+/// mac.handle(&[]);
+/// assert!(mac.uplink_commands().is_empty());
+///
+/// assert!(mac.request(UplinkMacCommand::LinkCheckReq));
+/// mac.uplink_sent();
+/// mac.handle(&[0x02, 0x0a, 0x01]);
+/// assert_eq!(mac.link_check().map(|l| (l.margin, l.gw_cnt)), Some((10, 1)));
+///
+/// // A new session keeps our battery level and margin, but nothing else.
+/// mac.handle(&[0x04, 0x03, 0x08, 0x02]);
+/// mac.rejoined();
+/// assert_eq!((mac.max_duty_cycle(), mac.rx_delay_s(), mac.link_check()), (0, 1, None));
+/// assert!(mac.uplink_commands().is_empty());
+/// mac.handle(&[0x06]);
+/// assert_eq!(mac.uplink_commands(), &[UplinkMacCommand::DevStatusAns { battery: 128, margin: 0 }]);
+/// ```
+pub struct MacCommands {
+    uplink_commands: Vec<UplinkMacCommand, MAX_FOPTS_LEN>,
+    battery: u8,
+    margin: i8,
+    max_duty_cycle: u8,
+    rx_delay_s: u8,
+    link_check: Option<LinkCheck>,
+}
+
+impl MacCommands {
+    pub fn new() -> Self {
+        MacCommands {
+            uplink_commands: Vec::new(),
+            battery: BATTERY_UNKNOWN,
+            margin: 0,
+            max_duty_cycle: 0,
+            rx_delay_s: 1,
+            link_check: None,
+        }
+    }
+
+    /// Forget what was asked of us during the last session, as well as what
+    /// we asked, on joining afresh.
+    pub fn rejoined(&mut self) {
+        self.uplink_commands.clear();
+        self.max_duty_cycle = 0;
+        self.rx_delay_s = 1;
+        self.link_check = None;
+    }
+
+    /// Our battery level to report in a DevStatusAns, where 0 is external power,
+    /// 1 to 254 is the level, and 255 is unknown.
+    pub fn set_battery(&mut self, battery: u8) {
+        self.battery = battery;
+    }
+
+    /// The demodulation margin to report in a DevStatusAns.
+    pub fn set_margin(&mut self, margin: i8) {
+        self.margin = margin;
+    }
+
+    /// Process the FOpts or FPort 0 FRMPayload of a downlink. Receiving a downlink
+    /// acknowledges any sticky answers we've been sending.
+    pub fn handle(&mut self, commands: &[u8]) {
+        self.uplink_commands.retain(|c| !c.is_sticky());
+        self.process(commands);
+    }
+
+    /// Convenience for processing both the FOpts and any FPort 0 payload of a
+    /// downlink.
+    pub fn handle_downlink(&mut self, downlink: &Downlink) {
+        self.handle(&downlink.fopts);
+        if downlink.f_port == Some(0) {
+            self.process(&downlink.frm_payload);
+        }
+    }
+
+    fn process(&mut self, commands: &[u8]) {
+        for command in parse_downlink_commands(commands) {
+            match command {
+                DownlinkMacCommand::LinkCheckAns { margin, gw_cnt } => {
+                    self.link_check = Some(LinkCheck { margin, gw_cnt })
+                }
+                DownlinkMacCommand::DutyCycleReq { max_duty_cycle } => {
+                    self.max_duty_cycle = max_duty_cycle;
+                    self.request(UplinkMacCommand::DutyCycleAns);
+                }
+                DownlinkMacCommand::DevStatusReq => {
+                    self.request(UplinkMacCommand::DevStatusAns {
+                        battery: self.battery,
+                        margin: self.margin,
+                    });
+                }
+                DownlinkMacCommand::RxTimingSetupReq { delay } => {
+                    self.rx_delay_s = delay.max(1);
+                    self.request(UplinkMacCommand::RxTimingSetupAns);
+                }
+                DownlinkMacCommand::Unsupported { .. } => (),
+            }
+        }
+    }
+
+    /// Queue a command for the next uplink. Returns false if there's no room
+    /// left in FOpts.
+    ///
+    /// ```
+    /// use app::mac::{MacCommands, UplinkMacCommand};
+    ///
+    /// let mut mac = MacCommands::new();
+    /// for battery in 0..5 {
+    ///     assert!(mac.request(UplinkMacCommand::DevStatusAns { battery, margin: 0 }));
+    /// }
+    /// assert!(!mac.request(UplinkMacCommand::LinkCheckReq));
+    /// ```
+    pub fn request(&mut self, command: UplinkMacCommand) -> bool {
+        if self.uplink_commands.contains(&command) {
+            return true;
+        }
+        let len: usize = self
+            .uplink_commands
+            .iter()
+            .map(|c| c.to_bytes().len())
+            .sum();
+        if len + command.to_bytes().len() > MAX_FOPTS_LEN {
+            return false;
+        }
+        self.uplink_commands.push(command).is_ok()
+    }
+
+    /// The commands to convey in the FOpts of the next uplink.
+    pub fn uplink_commands(&self) -> &[UplinkMacCommand] {
+        &self.uplink_commands
+    }
+
+    /// Record that the uplink commands have been sent, retaining only those
+    /// that are sticky.
+    pub fn uplink_sent(&mut self) {
+        self.uplink_commands.retain(|c| c.is_sticky());
+    }
+
+    /// The aggregated duty cycle is 1 / 2^max_duty_cycle.
+    pub fn max_duty_cycle(&self) -> u8 {
+        self.max_duty_cycle
+    }
+
+    /// Lengthen the interval between our sends such that we keep to our
+    /// aggregated duty cycle, where the interval given is that of an
+    /// unrestricted one.
+    ///
+    /// ```
+    /// use app::mac::MacCommands;
+    ///
+    /// let mut mac = MacCommands::new();
+    /// assert_eq!(mac.throttle_ms(60_000), 60_000);
+    /// mac.handle(&[0x04, 0x03]);
+    /// assert_eq!(mac.throttle_ms(60_000), 480_000);
+    /// mac.handle(&[0x04, 0x0f]);
+    /// assert_eq!(mac.throttle_ms(600_000), u32::MAX);
+    /// ```
+    pub fn throttle_ms(&self, ms: u32) -> u32 {
+        ms.saturating_mul(1 << self.max_duty_cycle)
+    }
+
+    /// The delay between the end of an uplink and the first receive window.
+    pub fn rx_delay_s(&self) -> u8 {
+        self.rx_delay_s
+    }
+
+    pub fn link_check(&self) -> Option<LinkCheck> {
+        self.link_check
+    }
+}
+
+impl Default for MacCommands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adapts an uplink command for `lorawan_encoding`'s frame creator.
+pub(crate) struct FOpt(Vec<u8, 3>);
+
+impl From<&UplinkMacCommand> for FOpt {
+    fn from(command: &UplinkMacCommand) -> Self {
+        FOpt(command.to_bytes())
+    }
+}
+
+impl SerializableMacCommand for FOpt {
+    fn payload_bytes(&self) -> &[u8] {
+        &self.0[1..]
+    }
+
+    fn cid(&self) -> u8 {
+        self.0[0]
+    }
+
+    fn payload_len(&self) -> usize {
+        self.0.len() - 1
+    }
+}
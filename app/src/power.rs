@@ -0,0 +1,237 @@
+//! Power saving between uplinks. With Power Saving Mode (PSM), the modem
+//! remains reachable for an active time once idle, and then sleeps until its
+//! periodic tracking area update (TAU). With extended discontinuous reception
+//! (eDRX), the modem listens for paging for a paging time window (PTW) once
+//! every eDRX cycle.
+//!
+//! We request values of the network, which grants what it will. Values are
+//! conveyed in the bit strings of 3GPP TS 24.008 and 27.007, which we encode
+//! and decode here.
+
+use serde::{Deserialize, Serialize};
+
+use crate::system_mode::SystemMode;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Psm {
+    pub periodic_tau_s: u32,
+    pub active_time_s: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Edrx {
+    pub cycle_ms: u32,
+    pub ptw_ms: u32,
+}
+
+// GPRS timer units in seconds, by their unit bits, and in ascending order.
+
+const PERIODIC_TAU_UNITS: [(u8, u32); 7] = [
+    (0b011, 2),
+    (0b100, 30),
+    (0b101, 60),
+    (0b000, 10 * 60),
+    (0b001, 60 * 60),
+    (0b010, 10 * 60 * 60),
+    (0b110, 320 * 60 * 60),
+];
+const ACTIVE_TIME_UNITS: [(u8, u32); 3] = [(0b000, 2), (0b001, 60), (0b010, 6 * 60)];
+const DEACTIVATED: u8 = 0b111;
+
+// Encode a duration with the finest unit able to convey it, rounding up.
+
+fn encode_timer(units: &[(u8, u32)], s: u32) -> u8 {
+    let (unit_bits, value) = units
+        .iter()
+        .map(|(unit_bits, unit_s)| (*unit_bits, s.div_ceil(*unit_s)))
+        .find(|(_, value)| *value <= 0b11111)
+        .unwrap_or((units[units.len() - 1].0, 0b11111));
+    unit_bits << 5 | value as u8
+}
+
+fn decode_timer(units: &[(u8, u32)], timer: u8) -> Option<u32> {
+    let unit_bits = timer >> 5;
+    let value = u32::from(timer & 0b11111);
+    if unit_bits == DEACTIVATED {
+        return None;
+    }
+    units
+        .iter()
+        .find(|(bits, _)| *bits == unit_bits)
+        .map(|(_, unit_s)| value * unit_s)
+}
+
+/// The requested periodic TAU (T3412 extended) as a GPRS Timer 3.
+///
+/// ```
+/// use app::power::{decode_periodic_tau, encode_periodic_tau};
+///
+/// assert_eq!(encode_periodic_tau(60), 0b011_11110); // 30 * 2s
+/// assert_eq!(encode_periodic_tau(3601), 0b000_00111); // 7 * 10 min, rounded up
+/// assert_eq!(encode_periodic_tau(12 * 3600), 0b001_01100); // 12 * 1 hour
+/// assert_eq!(encode_periodic_tau(u32::MAX), 0b110_11111);
+/// assert_eq!(decode_periodic_tau(0b001_01100), Some(12 * 3600));
+/// assert_eq!(decode_periodic_tau(0b111_00000), None);
+/// ```
+pub fn encode_periodic_tau(s: u32) -> u8 {
+    encode_timer(&PERIODIC_TAU_UNITS, s)
+}
+
+pub fn decode_periodic_tau(timer: u8) -> Option<u32> {
+    decode_timer(&PERIODIC_TAU_UNITS, timer)
+}
+
+/// The requested active time (T3324) as a GPRS Timer 2. Units that aren't
+/// defined are to be read as minutes.
+///
+/// ```
+/// use app::power::{decode_active_time, encode_active_time};
+///
+/// assert_eq!(encode_active_time(0), 0b000_00000);
+/// assert_eq!(encode_active_time(10), 0b000_00101); // 5 * 2s
+/// assert_eq!(encode_active_time(120), 0b001_00010); // 2 * 1 min
+/// assert_eq!(decode_active_time(0b001_00010), Some(120));
+/// assert_eq!(decode_active_time(0b011_00010), Some(120));
+/// assert_eq!(decode_active_time(0b111_00000), None);
+/// ```
+pub fn encode_active_time(s: u32) -> u8 {
+    encode_timer(&ACTIVE_TIME_UNITS, s)
+}
+
+pub fn decode_active_time(timer: u8) -> Option<u32> {
+    match timer >> 5 {
+        DEACTIVATED => None,
+        0b000..=0b010 => decode_timer(&ACTIVE_TIME_UNITS, timer),
+        _ => Some(u32::from(timer & 0b11111) * 60),
+    }
+}
+
+// eDRX cycles by their value bits, with those permitted for NB-IoT.
+
+const EDRX_CYCLES_MS: [u32; 16] = [
+    5_120, 10_240, 20_480, 40_960, 61_440, 81_920, 102_400, 122_880, 143_360, 163_840, 327_680,
+    655_360, 1_310_720, 2_621_440, 5_242_880, 10_485_760,
+];
+const NB_IOT_EDRX_CYCLES: [u8; 10] = [2, 3, 5, 9, 10, 11, 12, 13, 14, 15];
+
+/// The value bits of the longest eDRX cycle available to a system mode that
+/// is no longer than that requested, or of the shortest.
+///
+/// ```
+/// use app::power::{decode_edrx_cycle, encode_edrx_cycle};
+/// use app::system_mode::SystemMode;
+///
+/// assert_eq!(encode_edrx_cycle(SystemMode::LteM, 81_920), 0b0101);
+/// assert_eq!(encode_edrx_cycle(SystemMode::LteM, 100_000), 0b0101);
+/// assert_eq!(encode_edrx_cycle(SystemMode::LteM, 0), 0b0000);
+/// assert_eq!(encode_edrx_cycle(SystemMode::NbIot, 143_360), 0b0101);
+/// assert_eq!(encode_edrx_cycle(SystemMode::NbIot, 0), 0b0010);
+/// assert_eq!(decode_edrx_cycle(0b1111), 10_485_760);
+/// ```
+pub fn encode_edrx_cycle(mode: SystemMode, cycle_ms: u32) -> u8 {
+    let available = |bits: &u8| match mode {
+        SystemMode::LteM => true,
+        SystemMode::NbIot => NB_IOT_EDRX_CYCLES.contains(bits),
+    };
+    let mut candidates = (0..16).filter(available);
+    let shortest = candidates.next().unwrap_or(0);
+    candidates
+        .rev()
+        .find(|bits| EDRX_CYCLES_MS[*bits as usize] <= cycle_ms)
+        .unwrap_or(shortest)
+}
+
+pub fn decode_edrx_cycle(bits: u8) -> u32 {
+    EDRX_CYCLES_MS[usize::from(bits & 0b1111)]
+}
+
+fn ptw_step_ms(mode: SystemMode) -> u32 {
+    match mode {
+        SystemMode::LteM => 1_280,
+        SystemMode::NbIot => 2_560,
+    }
+}
+
+/// The value bits of the longest paging time window no longer than that
+/// requested, or of the shortest.
+///
+/// ```
+/// use app::power::{decode_ptw, encode_ptw};
+/// use app::system_mode::SystemMode;
+///
+/// assert_eq!(encode_ptw(SystemMode::LteM, 3_840), 0b0010);
+/// assert_eq!(encode_ptw(SystemMode::NbIot, 3_840), 0b0000);
+/// assert_eq!(encode_ptw(SystemMode::NbIot, u32::MAX), 0b1111);
+/// assert_eq!(decode_ptw(SystemMode::NbIot, 0b0001), 5_120);
+/// ```
+pub fn encode_ptw(mode: SystemMode, ptw_ms: u32) -> u8 {
+    (ptw_ms / ptw_step_ms(mode)).clamp(1, 16) as u8 - 1
+}
+
+pub fn decode_ptw(mode: SystemMode, bits: u8) -> u32 {
+    (u32::from(bits & 0b1111) + 1) * ptw_step_ms(mode)
+}
+
+// The quoted bit strings of AT command responses.
+
+fn bits(field: &str) -> Option<u8> {
+    let field = field.trim().trim_matches('"');
+    if field.is_empty() || field.len() > 8 {
+        return None;
+    }
+    u8::from_str_radix(field, 2).ok()
+}
+
+/// The PSM values granted by the network as reported by `AT+CEREG?` once
+/// subscribed with `AT+CEREG=5`.
+///
+/// ```
+/// use app::power::{granted_psm, Psm};
+///
+/// assert_eq!(
+///     granted_psm("+CEREG: 5,1,\"002F\",\"0012BEEF\",9,,,\"00000101\",\"00101100\""),
+///     Some(Psm { periodic_tau_s: 12 * 3600, active_time_s: 10 })
+/// );
+/// assert_eq!(
+///     granted_psm("+CEREG: 5,1,\"002F\",\"0012BEEF\",9,,,\"11100000\",\"11100000\""),
+///     None
+/// );
+/// assert_eq!(granted_psm("+CEREG: 5,2"), None);
+/// ```
+pub fn granted_psm(response: &str) -> Option<Psm> {
+    let mut fields = response.strip_prefix("+CEREG:")?.split(',').skip(7);
+    let active_time_s = decode_active_time(bits(fields.next()?)?)?;
+    let periodic_tau_s = decode_periodic_tau(bits(fields.next()?)?)?;
+    Some(Psm {
+        periodic_tau_s,
+        active_time_s,
+    })
+}
+
+/// The eDRX values granted by the network as reported by `AT+CEDRXRDP`.
+///
+/// ```
+/// use app::power::{granted_edrx, Edrx};
+///
+/// assert_eq!(
+///     granted_edrx("+CEDRXRDP: 5,\"0101\",\"0101\",\"0001\""),
+///     Some(Edrx { cycle_ms: 81_920, ptw_ms: 5_120 })
+/// );
+/// assert_eq!(
+///     granted_edrx("+CEDRXRDP: 4,\"0101\",\"0101\",\"0001\""),
+///     Some(Edrx { cycle_ms: 81_920, ptw_ms: 2_560 })
+/// );
+/// assert_eq!(granted_edrx("+CEDRXRDP: 0"), None); // Not in use
+/// ```
+pub fn granted_edrx(response: &str) -> Option<Edrx> {
+    let mut fields = response.strip_prefix("+CEDRXRDP:")?.split(',');
+    let mode = match fields.next()?.trim() {
+        "4" => SystemMode::LteM,
+        "5" => SystemMode::NbIot,
+        _ => return None,
+    };
+    let _requested = fields.next()?;
+    let cycle_ms = decode_edrx_cycle(bits(fields.next()?)?);
+    let ptw_ms = decode_ptw(mode, bits(fields.next()?)?);
+    Some(Edrx { cycle_ms, ptw_ms })
+}
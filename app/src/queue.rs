@@ -0,0 +1,273 @@
+//! Uplinks that we have yet to send, retained while the network is unavailable.
+//! We queue application payloads rather than frames, given that a frame's
+//! counter and MAC commands are only known once it is sent.
+//!
+//! The queue is bounded. Once full, the oldest uplink is evicted, and may then
+//! be spilled to flash. Spilled uplinks are older than any that are queued, so
+//! they are returned to the oldest end of the queue as room allows.
+//!
+//! A spilled uplink is a record that may be written to a flash page with the
+//! page then only erased once all of its records have been consumed:
+//!
+//! Start |   End | Description
+//!     0 |     0 | Payload length
+//!     1 |     1 | FPort
+//!     2 |     3 | Reserved, zero
+//!     4 |     7 | Consumed marker, erased until the record has been taken
+//!     8 |   229 | Payload, padded
+//!   230 |   231 | Padding
+
+use heapless::Vec;
+
+use crate::codec::{Payload, MAX_PAYLOAD_LEN};
+
+/// An application payload awaiting an uplink.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Uplink {
+    pub f_port: u8,
+    pub payload: Payload,
+}
+
+/// The order in which queued uplinks are sent once the network is available.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DrainOrder {
+    /// Send uplinks in the order that they were queued.
+    OldestFirst,
+    /// Send the most recent uplink first, favouring current readings.
+    NewestFirst,
+}
+
+/// A bounded queue of uplinks.
+///
+/// ```
+/// use app::codec::Payload;
+/// use app::queue::{DrainOrder, Uplink, UplinkQueue};
+///
+/// let uplink = |b| Uplink { f_port: 1, payload: Payload::from_slice(&[b]).unwrap() };
+///
+/// let mut queue: UplinkQueue<2> = UplinkQueue::new(DrainOrder::OldestFirst);
+/// assert_eq!(queue.push(uplink(1)), None);
+/// assert_eq!(queue.push(uplink(2)), None);
+/// assert_eq!(queue.push(uplink(3)), Some(uplink(1))); // Evicted
+/// assert_eq!(queue.pop(), Some(uplink(2)));
+/// assert_eq!(queue.restore(uplink(2)), None); // Couldn't send it
+/// assert_eq!(queue.pop(), Some(uplink(2)));
+/// assert_eq!(queue.unspill(uplink(1)), None);
+/// assert_eq!(queue.unspill(uplink(0)), Some(uplink(0))); // No room
+/// assert_eq!(queue.pop(), Some(uplink(1)));
+/// assert_eq!(queue.pop(), Some(uplink(3)));
+/// assert_eq!(queue.pop(), None);
+///
+/// let mut queue: UplinkQueue<2> = UplinkQueue::new(DrainOrder::NewestFirst);
+/// queue.push(uplink(1));
+/// queue.push(uplink(2));
+/// assert_eq!(queue.pop(), Some(uplink(2)));
+/// assert_eq!(queue.restore(uplink(2)), None);
+/// assert_eq!(queue.pop(), Some(uplink(2)));
+/// assert_eq!(queue.pop(), Some(uplink(1)));
+/// assert!(queue.is_empty());
+/// ```
+pub struct UplinkQueue<const N: usize> {
+    // Oldest first
+    uplinks: Vec<Uplink, N>,
+    order: DrainOrder,
+}
+
+impl<const N: usize> UplinkQueue<N> {
+    pub fn new(order: DrainOrder) -> Self {
+        UplinkQueue {
+            uplinks: Vec::new(),
+            order,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.uplinks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uplinks.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.uplinks.is_full()
+    }
+
+    /// Queue an uplink as the newest, returning the oldest if it had to be
+    /// evicted to make room.
+    pub fn push(&mut self, uplink: Uplink) -> Option<Uplink> {
+        let evicted = if self.uplinks.is_full() {
+            self.pop_oldest()
+        } else {
+            None
+        };
+        let _ = self.uplinks.push(uplink);
+        evicted
+    }
+
+    /// Take the next uplink to send, as per our drain order.
+    pub fn pop(&mut self) -> Option<Uplink> {
+        match self.order {
+            DrainOrder::OldestFirst => self.pop_oldest(),
+            DrainOrder::NewestFirst => self.uplinks.pop(),
+        }
+    }
+
+    /// Return an uplink taken with `pop` that could not be sent, so that it is
+    /// the next to send once more. Should uplinks have been pushed in the
+    /// meantime, the oldest may be evicted as per `push`.
+    pub fn restore(&mut self, uplink: Uplink) -> Option<Uplink> {
+        match self.order {
+            DrainOrder::OldestFirst => {
+                if self.uplinks.is_full() {
+                    Some(uplink)
+                } else {
+                    self.push_oldest(uplink);
+                    None
+                }
+            }
+            DrainOrder::NewestFirst => self.push(uplink),
+        }
+    }
+
+    /// Queue an uplink returned from flash as the oldest, handing it back if
+    /// there is no room.
+    pub fn unspill(&mut self, uplink: Uplink) -> Option<Uplink> {
+        if self.uplinks.is_full() {
+            Some(uplink)
+        } else {
+            self.push_oldest(uplink);
+            None
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<Uplink> {
+        if self.uplinks.is_empty() {
+            return None;
+        }
+        self.uplinks.rotate_left(1);
+        self.uplinks.pop()
+    }
+
+    fn push_oldest(&mut self, uplink: Uplink) {
+        if self.uplinks.push(uplink).is_ok() {
+            self.uplinks.rotate_right(1);
+        }
+    }
+}
+
+/// The length of a spilled uplink in flash. A multiple of the flash word size.
+pub const SPILL_RECORD_LEN: usize = 232;
+
+const PAYLOAD_OFFSET: usize = 8;
+const ERASED: u8 = 0xff;
+
+/// The state of a spilled uplink record as found in flash.
+#[derive(Debug, PartialEq)]
+pub enum SpillState {
+    Erased,
+    Consumed,
+    Pending,
+}
+
+/// The word to write at this offset within a record in order to mark it as
+/// consumed.
+pub const CONSUMED_OFFSET: usize = 4;
+pub const CONSUMED: [u8; 4] = [0; 4];
+
+impl Uplink {
+    /// ```
+    /// use app::codec::Payload;
+    /// use app::queue::{SpillState, Uplink, CONSUMED, CONSUMED_OFFSET};
+    ///
+    /// let uplink = Uplink { f_port: 2, payload: Payload::from_slice(&[0x01, 0x02]).unwrap() };
+    /// let mut record = uplink.to_spill_record();
+    /// assert_eq!(&record[..10], &[0x02, 0x02, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01, 0x02]);
+    /// assert_eq!(Uplink::spill_state(&record), SpillState::Pending);
+    /// assert_eq!(Uplink::from_spill_record(&record), Some(uplink));
+    ///
+    /// record[CONSUMED_OFFSET..CONSUMED_OFFSET + 4].copy_from_slice(&CONSUMED);
+    /// assert_eq!(Uplink::spill_state(&record), SpillState::Consumed);
+    /// assert_eq!(Uplink::from_spill_record(&record), None);
+    ///
+    /// assert_eq!(Uplink::spill_state(&[0xff; 232]), SpillState::Erased);
+    /// ```
+    pub fn to_spill_record(&self) -> [u8; SPILL_RECORD_LEN] {
+        let mut record = [0; SPILL_RECORD_LEN];
+        record[0] = self.payload.len() as u8;
+        record[1] = self.f_port;
+        record[CONSUMED_OFFSET..CONSUMED_OFFSET + 4].copy_from_slice(&[ERASED; 4]);
+        record[PAYLOAD_OFFSET..PAYLOAD_OFFSET + self.payload.len()].copy_from_slice(&self.payload);
+        record
+    }
+
+    pub fn spill_state(record: &[u8; SPILL_RECORD_LEN]) -> SpillState {
+        if record[..CONSUMED_OFFSET] == [ERASED; 4] {
+            SpillState::Erased
+        } else if record[CONSUMED_OFFSET..CONSUMED_OFFSET + 4] != [ERASED; 4]
+            || record[0] as usize > MAX_PAYLOAD_LEN
+        {
+            // Records that we can't make sense of are treated as consumed.
+            SpillState::Consumed
+        } else {
+            SpillState::Pending
+        }
+    }
+
+    /// Returns the uplink of a pending record.
+    pub fn from_spill_record(record: &[u8; SPILL_RECORD_LEN]) -> Option<Self> {
+        if Self::spill_state(record) != SpillState::Pending {
+            return None;
+        }
+        let len = record[0] as usize;
+        Some(Uplink {
+            f_port: record[1],
+            payload: Payload::from_slice(&record[PAYLOAD_OFFSET..PAYLOAD_OFFSET + len]).ok()?,
+        })
+    }
+}
+
+/// Scan a page of spill records, returning the offset of the newest pending
+/// record along with its uplink, and the offset at which the next record
+/// should be written. An offset beyond the last whole record indicates that
+/// the page is full.
+///
+/// ```
+/// use app::codec::Payload;
+/// use app::queue::{find_newest_pending, Uplink, SPILL_RECORD_LEN, CONSUMED, CONSUMED_OFFSET};
+///
+/// let uplink = |b| Uplink { f_port: 1, payload: Payload::from_slice(&[b]).unwrap() };
+///
+/// let mut page = [0xff_u8; 3 * SPILL_RECORD_LEN];
+/// assert_eq!(find_newest_pending(&page), (None, 0));
+///
+/// page[..SPILL_RECORD_LEN].copy_from_slice(&uplink(1).to_spill_record());
+/// page[SPILL_RECORD_LEN..2 * SPILL_RECORD_LEN].copy_from_slice(&uplink(2).to_spill_record());
+/// assert_eq!(
+///     find_newest_pending(&page),
+///     (Some((SPILL_RECORD_LEN, uplink(2))), 2 * SPILL_RECORD_LEN)
+/// );
+///
+/// page[SPILL_RECORD_LEN + CONSUMED_OFFSET..SPILL_RECORD_LEN + CONSUMED_OFFSET + 4].copy_from_slice(&CONSUMED);
+/// assert_eq!(find_newest_pending(&page), (Some((0, uplink(1))), 2 * SPILL_RECORD_LEN));
+/// ```
+pub fn find_newest_pending(page: &[u8]) -> (Option<(usize, Uplink)>, usize) {
+    let mut newest = None;
+    let mut next = page.len() - page.len() % SPILL_RECORD_LEN;
+    for (i, chunk) in page.chunks_exact(SPILL_RECORD_LEN).enumerate() {
+        let mut record = [0; SPILL_RECORD_LEN];
+        record.copy_from_slice(chunk);
+        match Uplink::spill_state(&record) {
+            SpillState::Erased => {
+                next = i * SPILL_RECORD_LEN;
+                break;
+            }
+            SpillState::Consumed => (),
+            SpillState::Pending => newest = Some((i * SPILL_RECORD_LEN, record)),
+        }
+    }
+    (
+        newest.and_then(|(offset, record)| Some((offset, Uplink::from_spill_record(&record)?))),
+        next,
+    )
+}
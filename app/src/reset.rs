@@ -0,0 +1,120 @@
+//! Why we last reset, reported in an uplink on its own FPort once we've
+//! started, so that the server can tell a device that hangs, and is reset by
+//! our watchdog, from one that panics or loses power.
+
+use crate::codec::{EncodeError, Payload, PayloadCodec};
+
+/// The FPort on which our reset reason is sent.
+pub const RESET_F_PORT: u8 = 4;
+
+// The bits of the nRF9160's RESETREAS register.
+
+const RESETPIN: u32 = 1 << 0;
+const DOG: u32 = 1 << 1;
+const SREQ: u32 = 1 << 4;
+const LOCKUP: u32 = 1 << 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetReason {
+    PowerOn,
+    /// The reset pin, as pressed when debugging.
+    Pin,
+    Watchdog,
+    /// A soft reset or lockup, as a panic results in.
+    Panic,
+    /// The soft reset on leaving our console.
+    Console,
+    /// Waking from System OFF, or a debugger.
+    Other,
+}
+
+impl ResetReason {
+    /// Decode the RESETREAS register, which accumulates reasons until
+    /// cleared. Nothing being noted means that we've powered on.
+    ///
+    /// ```
+    /// use app::reset::ResetReason;
+    ///
+    /// assert_eq!(ResetReason::from_resetreas(0), ResetReason::PowerOn);
+    /// assert_eq!(ResetReason::from_resetreas(0b0000_0001), ResetReason::Pin);
+    /// assert_eq!(ResetReason::from_resetreas(0b0000_0010), ResetReason::Watchdog);
+    /// assert_eq!(ResetReason::from_resetreas(0b0001_0000), ResetReason::Panic);
+    /// assert_eq!(ResetReason::from_resetreas(0b0010_0000), ResetReason::Panic);
+    /// assert_eq!(ResetReason::from_resetreas(0b0000_0100), ResetReason::Other);
+    ///
+    /// // The watchdog takes precedence
+    /// assert_eq!(ResetReason::from_resetreas(0b0001_0011), ResetReason::Watchdog);
+    /// ```
+    pub fn from_resetreas(bits: u32) -> Self {
+        if bits & DOG != 0 {
+            ResetReason::Watchdog
+        } else if bits & (SREQ | LOCKUP) != 0 {
+            ResetReason::Panic
+        } else if bits & RESETPIN != 0 {
+            ResetReason::Pin
+        } else if bits == 0 {
+            ResetReason::PowerOn
+        } else {
+            ResetReason::Other
+        }
+    }
+}
+
+/// Our reset reason is conveyed as a single byte, followed by our crash as
+/// laid out by `crash::CrashCodec` should we have panicked:
+///
+/// Value | Reason
+///     0 | Power on
+///     1 | Reset pin
+///     2 | Watchdog
+///     3 | Panic
+///     4 | Other
+///     5 | Console
+///
+/// ```
+/// use app::codec::{Payload, PayloadCodec};
+/// use app::reset::{ResetCodec, ResetReason};
+///
+/// let mut payload = Payload::new();
+/// ResetCodec.encode(&ResetReason::Watchdog, &mut payload).unwrap();
+/// ResetCodec.encode(&ResetReason::Console, &mut payload).unwrap();
+/// assert_eq!(&payload[..], &[0x02, 0x05]);
+/// ```
+pub struct ResetCodec;
+
+impl PayloadCodec<ResetReason> for ResetCodec {
+    fn encode(&self, value: &ResetReason, payload: &mut Payload) -> Result<(), EncodeError> {
+        let value = match value {
+            ResetReason::PowerOn => 0,
+            ResetReason::Pin => 1,
+            ResetReason::Watchdog => 2,
+            ResetReason::Panic => 3,
+            ResetReason::Other => 4,
+            ResetReason::Console => 5,
+        };
+        payload.push(value).map_err(|_| EncodeError::Overflow)
+    }
+}
+
+// The watchdog counts ticks of the 32.768 kHz low frequency clock, with at
+// least this many before it resets us.
+
+const WATCHDOG_HZ: u64 = 32_768;
+const MIN_WATCHDOG_TICKS: u32 = 0xf;
+
+/// The ticks of our watchdog's timeout. We feed the watchdog each time we've
+/// woken and done our work successfully, so it must allow for waking, and then
+/// our work taking up to `slack_ms`, which attaching may well do.
+///
+/// ```
+/// use app::reset::watchdog_ticks;
+///
+/// assert_eq!(watchdog_ticks(60_000, 30_000), 90 * 32_768);
+/// assert_eq!(watchdog_ticks(0, 0), 0xf);
+/// assert_eq!(watchdog_ticks(u32::MAX, u32::MAX), u32::MAX);
+/// ```
+pub fn watchdog_ticks(wake_ms: u32, slack_ms: u32) -> u32 {
+    let timeout_ms = u64::from(wake_ms) + u64::from(slack_ms);
+    let ticks = timeout_ms * WATCHDOG_HZ / 1000;
+    ticks.clamp(MIN_WATCHDOG_TICKS.into(), u32::MAX.into()) as u32
+}
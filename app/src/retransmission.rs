@@ -0,0 +1,148 @@
+//! Confirmed uplinks are retransmitted until the network server acknowledges
+//! them, or until we've used up our transmissions. This follows the NbTrans
+//! notion of LoRaWAN where the same frame, with the same frame counter, is
+//! sent a number of times. We back off exponentially between attempts so as
+//! not to flood a network that is perhaps congested.
+
+/// How many times a confirmed frame is transmitted, and how long to wait
+/// between transmissions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetransmissionPolicy {
+    /// The total number of transmissions of a frame, including the first.
+    pub nb_trans: u8,
+    /// The delay before the first retransmission. Each subsequent
+    /// retransmission doubles this delay.
+    pub backoff_ms: u32,
+    /// The maximum delay between any two retransmissions.
+    pub max_backoff_ms: u32,
+}
+
+impl RetransmissionPolicy {
+    pub const fn new(nb_trans: u8, backoff_ms: u32) -> Self {
+        RetransmissionPolicy {
+            nb_trans,
+            backoff_ms,
+            max_backoff_ms: 60 * 1000,
+        }
+    }
+}
+
+impl Default for RetransmissionPolicy {
+    fn default() -> Self {
+        Self::new(3, 2000)
+    }
+}
+
+/// The state of a confirmed uplink.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Pending,
+    Acked,
+    Failed,
+}
+
+/// Tracks the transmissions of a single confirmed frame. Drive it by calling
+/// `next_attempt` to determine whether, and when, to transmit, and `ack` when
+/// a downlink with its ACK bit set has been received.
+///
+/// ```
+/// use app::retransmission::{Outcome, Retransmission, RetransmissionPolicy};
+///
+/// let mut r = Retransmission::new(RetransmissionPolicy::new(3, 1000));
+/// assert_eq!(r.next_attempt(), Some(0));
+/// assert_eq!(r.next_attempt(), Some(1000));
+/// r.ack();
+/// assert_eq!(r.next_attempt(), None);
+/// assert_eq!(r.outcome(), Outcome::Acked);
+/// assert_eq!(r.attempts(), 2);
+/// ```
+pub struct Retransmission {
+    policy: RetransmissionPolicy,
+    attempts: u8,
+    acked: bool,
+    exhausted: bool,
+}
+
+impl Retransmission {
+    pub fn new(policy: RetransmissionPolicy) -> Self {
+        Retransmission {
+            policy,
+            attempts: 0,
+            acked: false,
+            exhausted: false,
+        }
+    }
+
+    /// Returns the delay in milliseconds to wait before the next transmission,
+    /// or `None` if we've been acknowledged or there are no more transmissions.
+    /// The first transmission is never delayed. An ack may still be received
+    /// after the last transmission, so we only consider the frame as failed
+    /// once this function has declined to transmit again.
+    ///
+    /// ```
+    /// use app::retransmission::{Outcome, Retransmission, RetransmissionPolicy};
+    ///
+    /// let mut r = Retransmission::new(RetransmissionPolicy {
+    ///     nb_trans: 4,
+    ///     backoff_ms: 1000,
+    ///     max_backoff_ms: 3000,
+    /// });
+    /// assert_eq!(r.next_attempt(), Some(0));
+    /// assert_eq!(r.next_attempt(), Some(1000));
+    /// assert_eq!(r.next_attempt(), Some(2000));
+    /// assert_eq!(r.next_attempt(), Some(3000));
+    /// assert_eq!(r.outcome(), Outcome::Pending);
+    /// assert_eq!(r.next_attempt(), None);
+    /// assert_eq!(r.outcome(), Outcome::Failed);
+    /// ```
+    pub fn next_attempt(&mut self) -> Option<u32> {
+        if self.acked {
+            return None;
+        }
+        if self.attempts >= self.policy.nb_trans.max(1) {
+            self.exhausted = true;
+            return None;
+        }
+        let delay_ms = match self.attempts {
+            0 => 0,
+            n => self
+                .policy
+                .backoff_ms
+                .saturating_mul(1 << u32::from(n - 1).min(31))
+                .min(self.policy.max_backoff_ms),
+        };
+        self.attempts += 1;
+        Some(delay_ms)
+    }
+
+    /// Record that the network server has acknowledged our frame. Acks
+    /// received before any transmission, or after we've given up, are ignored.
+    ///
+    /// ```
+    /// use app::retransmission::{Outcome, Retransmission, RetransmissionPolicy};
+    ///
+    /// let mut r = Retransmission::new(RetransmissionPolicy::default());
+    /// r.ack();
+    /// assert_eq!(r.outcome(), Outcome::Pending);
+    /// ```
+    pub fn ack(&mut self) {
+        if self.attempts > 0 && !self.exhausted {
+            self.acked = true;
+        }
+    }
+
+    /// The number of transmissions made so far.
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        if self.acked {
+            Outcome::Acked
+        } else if self.exhausted {
+            Outcome::Failed
+        } else {
+            Outcome::Pending
+        }
+    }
+}
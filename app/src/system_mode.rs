@@ -0,0 +1,94 @@
+//! The radio access technology that the modem attaches with. Not every region
+//! has NB-IoT coverage, so we may prefer NB-IoT and fall back to LTE-M should
+//! attaching repeatedly fail, and vice versa, rather than never attaching.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SystemMode {
+    NbIot,
+    LteM,
+}
+
+/// Which system modes we attach with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SystemModePolicy {
+    NbIot,
+    LteM,
+    /// Attach with NB-IoT, switching to LTE-M after this many consecutive
+    /// failed attaches, and back again should LTE-M fare no better.
+    PreferNbIot {
+        fallback_after: u8,
+    },
+}
+
+/// Decides the system mode of each attach as per our policy.
+///
+/// ```
+/// use app::system_mode::{SystemMode, SystemModePolicy, SystemModeSelector};
+///
+/// let mut selector = SystemModeSelector::new(SystemModePolicy::LteM);
+/// assert_eq!(selector.mode(), SystemMode::LteM);
+/// assert!(!selector.attach_failed());
+/// assert_eq!(selector.mode(), SystemMode::LteM);
+///
+/// let mut selector = SystemModeSelector::new(SystemModePolicy::PreferNbIot { fallback_after: 2 });
+/// assert_eq!(selector.mode(), SystemMode::NbIot);
+/// assert!(!selector.attach_failed());
+/// assert!(selector.attach_failed()); // Fall back
+/// assert_eq!(selector.mode(), SystemMode::LteM);
+///
+/// // Attaching resets the count of failures
+/// assert!(!selector.attach_failed());
+/// selector.attached();
+/// assert!(!selector.attach_failed());
+/// assert_eq!(selector.mode(), SystemMode::LteM);
+///
+/// // Returning to NB-IoT should LTE-M also fail
+/// assert!(selector.attach_failed());
+/// assert_eq!(selector.mode(), SystemMode::NbIot);
+/// ```
+pub struct SystemModeSelector {
+    policy: SystemModePolicy,
+    mode: SystemMode,
+    failures: u8,
+}
+
+impl SystemModeSelector {
+    pub fn new(policy: SystemModePolicy) -> Self {
+        let mode = match policy {
+            SystemModePolicy::LteM => SystemMode::LteM,
+            _ => SystemMode::NbIot,
+        };
+        SystemModeSelector {
+            policy,
+            mode,
+            failures: 0,
+        }
+    }
+
+    /// The mode to attach with.
+    pub fn mode(&self) -> SystemMode {
+        self.mode
+    }
+
+    pub fn attached(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Returns whether the mode has changed as a consequence.
+    pub fn attach_failed(&mut self) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        match self.policy {
+            SystemModePolicy::PreferNbIot { fallback_after } if self.failures >= fallback_after => {
+                self.mode = match self.mode {
+                    SystemMode::NbIot => SystemMode::LteM,
+                    SystemMode::LteM => SystemMode::NbIot,
+                };
+                self.failures = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,8 +1,8 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x00040000, LENGTH = 764K
-  CONFIG : ORIGIN = ORIGIN(FLASH) + LENGTH(FLASH), LENGTH = 4K /* 4K is the flash page size */
+  FLASH : ORIGIN = 0x00040000, LENGTH = 748K
+  CONFIG : ORIGIN = ORIGIN(FLASH) + LENGTH(FLASH), LENGTH = 20K /* 4K is the flash page size: spilled uplinks, frame counters in two pages, then two config slots, the last at 0xFF000 where our first release saved its config */
   RAM : ORIGIN = 0x20020000, LENGTH = 128K
 }
 
@@ -1,3 +1,14 @@
+use app::{
+    battery::LowBatteryPolicy,
+    config::{
+        Config, PayloadFormat, Protocol, Psk, PskIdentity, QueueOrder, Transport,
+        MAX_PSK_IDENTITY_LEN,
+    },
+    crash::Crash,
+    host::{parse_hostname, IpAddr, MAX_HOSTNAME_LEN},
+    power::{Edrx, Psm},
+    system_mode::SystemModePolicy,
+};
 use bsp::hal::{uarte::Instance, Timer, Uarte};
 use core::fmt::Write;
 use menu::{Item, ItemType, Menu, Parameter, Runner};
@@ -5,7 +16,7 @@ use nrf9160_hal::pac::NVMC_NS;
 use nrf_hal_common::{nvmc::Nvmc, pac::TIMER0_NS};
 use thingy_91_nrf9160_bsp::hal::uarte;
 
-use crate::config::Config;
+use crate::{power, watchdog::Watchdog};
 
 pub struct Console<'a, T>
 where
@@ -15,6 +26,8 @@ where
     nvmc: &'a mut Nvmc<NVMC_NS>,
     timer: &'a mut Timer<TIMER0_NS>,
     uarte: &'a mut Uarte<T>,
+    crash: Option<&'a Crash>,
+    watchdog: Option<Watchdog>,
 }
 
 impl<'a, T> Console<'a, T>
@@ -26,12 +39,16 @@ where
         nvmc: &'a mut Nvmc<NVMC_NS>,
         timer: &'a mut Timer<TIMER0_NS>,
         uarte: &'a mut Uarte<T>,
+        crash: Option<&'a Crash>,
+        watchdog: Option<Watchdog>,
     ) -> Self {
         Console {
             config,
             nvmc,
             timer,
             uarte,
+            crash,
+            watchdog,
         }
     }
 }
@@ -121,7 +138,7 @@ fn set_send_freq_ms<'a, T>(
     };
 }
 
-fn set_network_server_host<'a, T>(
+fn set_sample_freq_ms<'a, T>(
     _menu: &Menu<Console<'a, T>>,
     _item: &Item<Console<'a, T>>,
     args: &[&str],
@@ -129,22 +146,73 @@ fn set_network_server_host<'a, T>(
 ) where
     T: Instance,
 {
-    let mut iter = args[0].split('.');
-    let mut result = [None; 4];
-    for r in &mut result {
-        if let Some(v) = iter.next() {
-            if let Ok(v) = v.parse::<u8>() {
-                *r = Some(v);
-            }
-        } else {
-            break;
-        }
-    }
-    match result {
-        [Some(b0), Some(b1), Some(b2), Some(b3)] => {
-            context.config.network_server_host = Some([b0, b1, b2, b3]);
-        }
+    match args[0].parse::<u32>() {
+        Ok(v) => context.config.sample_frequency_ms = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_diagnostics_freq_ms<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<u32>() {
+        Ok(v) => context.config.diagnostics_frequency_ms = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_watchdog<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match (args[0], args[0].parse::<u32>()) {
+        ("off", _) => context.config.watchdog_slack_ms = None,
+        (_, Ok(v)) => context.config.watchdog_slack_ms = Some(v),
         _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_max_payload_len<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<u8>() {
+        Ok(v) => context.config.max_payload_len = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_network_server_host<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    if let Some(addr) = IpAddr::parse(args[0]) {
+        context.config.network_server_host = Some(addr);
+        context.config.network_server_name = None;
+        context.config.resolved_host = None;
+    } else if let Some(hostname) = parse_hostname(args[0]) {
+        context.config.network_server_host = None;
+        context.config.network_server_name = Some(hostname);
+        context.config.resolved_host = None;
+    } else {
+        writeln!(context, "Invalid").unwrap();
     }
 }
 
@@ -162,6 +230,303 @@ fn set_network_server_port<'a, T>(
     };
 }
 
+fn set_transport<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0] {
+        "udp" => context.config.transport = Transport::Udp,
+        "dtls" => context.config.transport = Transport::Dtls,
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_psk_identity<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    // The identity is quoted when given to the modem.
+    let mut identity = PskIdentity::new();
+    match (
+        identity.push_str(args[0]),
+        !args[0].is_empty() && args[0].chars().all(|c| c.is_ascii_graphic() && c != '"'),
+    ) {
+        (Ok(_), true) => context.config.psk_identity = Some(identity),
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_psk<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    let hex = args[0].trim_start_matches("0x");
+    let mut psk = Psk::new();
+    let valid = !hex.is_empty()
+        && hex.len() % 2 == 0
+        && hex.chars().all(|c| c.is_ascii_hexdigit())
+        && (0..hex.len()).step_by(2).all(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .ok()
+                .and_then(|b| psk.push(b).ok())
+                .is_some()
+        });
+    if valid {
+        context.config.psk = Some(psk);
+    } else {
+        writeln!(context, "Invalid").unwrap();
+    }
+}
+
+fn set_confirmed<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<bool>() {
+        Ok(v) => context.config.confirmed = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_nb_trans<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<u8>() {
+        Ok(v) if v > 0 => context.config.nb_trans = v,
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_dev_eui<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match (
+        u64::from_str_radix(args[0].trim_start_matches("0x"), 16),
+        args[0].trim_start_matches("0x").len() == 16,
+    ) {
+        (Ok(v), true) => context.config.dev_eui = Some(v),
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_join_eui<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match (
+        u64::from_str_radix(args[0].trim_start_matches("0x"), 16),
+        args[0].trim_start_matches("0x").len() == 16,
+    ) {
+        (Ok(v), true) => context.config.join_eui = Some(v),
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_app_key<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match (
+        u128::from_str_radix(args[0].trim_start_matches("0x"), 16),
+        args[0].len() == 32,
+    ) {
+        (Ok(v), true) => context.config.app_key = Some(v),
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_rejoin_after<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<u8>() {
+        Ok(v) => context.config.rejoin_after = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_protocol<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0] {
+        "raw" => context.config.protocol = Protocol::Raw,
+        "semtech" => context.config.protocol = Protocol::SemtechUdp,
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_payload_format<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0] {
+        "environmental" => context.config.payload_format = PayloadFormat::Environmental,
+        "lpp" => context.config.payload_format = PayloadFormat::CayenneLpp,
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_queue_order<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0] {
+        "oldest" => context.config.queue_order = QueueOrder::OldestFirst,
+        "newest" => context.config.queue_order = QueueOrder::NewestFirst,
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_spill_to_flash<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match args[0].parse::<bool>() {
+        Ok(v) => context.config.spill_to_flash = v,
+        Err(_) => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+// Devices fall back to LTE-M after this many failed attaches with NB-IoT, unless
+// told otherwise.
+
+const DEFAULT_FALLBACK_AFTER: u8 = 3;
+
+fn set_system_mode<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    let fallback_after = args
+        .get(1)
+        .map_or(Ok(DEFAULT_FALLBACK_AFTER), |arg| arg.parse::<u8>());
+    match (args[0], fallback_after) {
+        ("nbiot", _) => context.config.system_mode = SystemModePolicy::NbIot,
+        ("ltem", _) => context.config.system_mode = SystemModePolicy::LteM,
+        ("auto", Ok(fallback_after)) if fallback_after > 0 => {
+            context.config.system_mode = SystemModePolicy::PreferNbIot { fallback_after }
+        }
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_psm<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    let active_time_s = args.get(1).map(|arg| arg.parse::<u32>());
+    match (args[0], args[0].parse::<u32>(), active_time_s) {
+        ("off", _, None) => context.config.psm = None,
+        (_, Ok(periodic_tau_s), Some(Ok(active_time_s))) => {
+            context.config.psm = Some(Psm {
+                periodic_tau_s,
+                active_time_s,
+            })
+        }
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_edrx<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    let ptw_ms = args.get(1).map(|arg| arg.parse::<u32>());
+    match (args[0], args[0].parse::<u32>(), ptw_ms) {
+        ("off", _, None) => context.config.edrx = None,
+        (_, Ok(cycle_ms), Some(Ok(ptw_ms))) => {
+            context.config.edrx = Some(Edrx { cycle_ms, ptw_ms })
+        }
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
+fn set_low_battery<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match (args[0].parse(), args[1].parse(), args[2].parse::<u8>()) {
+        (Ok(low_mv), Ok(cutoff_mv), Ok(stretch)) if cutoff_mv <= low_mv && stretch > 0 => {
+            context.config.low_battery = LowBatteryPolicy {
+                low_mv,
+                cutoff_mv,
+                stretch,
+            }
+        }
+        _ => writeln!(context, "Invalid").unwrap(),
+    };
+}
+
 fn save<'a, T>(
     _menu: &Menu<Console<'a, T>>,
     _item: &Item<Console<'a, T>>,
@@ -171,9 +536,27 @@ fn save<'a, T>(
     T: Instance,
 {
     write!(context, "Saving to flash... ").unwrap();
-    match context.config.save(&mut context.nvmc) {
+    match context.config.save(context.nvmc) {
         Ok(_) => writeln!(context, "saved.").unwrap(),
-        Err(_) => writeln!(context, "there was a problem saving.").unwrap(),
+        Err(e) => writeln!(context, "there was a problem saving: {}.", e).unwrap(),
+    };
+}
+
+fn show_crash<'a, T>(
+    _menu: &Menu<Console<'a, T>>,
+    _item: &Item<Console<'a, T>>,
+    _args: &[&str],
+    context: &mut Console<'a, T>,
+) where
+    T: Instance,
+{
+    match context.crash {
+        Some(crash) => {
+            writeln!(context, "RESETS:\t\t\t {}", crash.resets).unwrap();
+            writeln!(context, "LOCATION:\t\t {}:{}", crash.file, crash.line).unwrap();
+            writeln!(context, "MESSAGE:\t\t {}", crash.message).unwrap();
+        }
+        None => writeln!(context, "No crash since last reporting").unwrap(),
     };
 }
 
@@ -191,29 +574,41 @@ fn show<'a, T>(
     if let Some(nwkskey) = config.nwkskey {
         writeln!(context, "NWKSKEY:\t\t 0x{:032X}", nwkskey).unwrap();
     } else {
-        writeln!(context, "NWKSKEY:\t\t REQUIRED!").unwrap();
+        writeln!(context, "NWKSKEY:\t\t REQUIRED for ABP!").unwrap();
     }
     if let Some(appskey) = config.appskey {
         writeln!(context, "APPSKEY:\t\t 0x{:032X}", appskey).unwrap();
     } else {
-        writeln!(context, "APPSKEY:\t\t REQUIRED!").unwrap();
+        writeln!(context, "APPSKEY:\t\t REQUIRED for ABP!").unwrap();
     }
     if let Some(iccid) = config.iccid {
         writeln!(context, "ICCID:\t\t\t {}", iccid).unwrap();
     } else {
-        writeln!(context, "ICCID:\t\t\t REQUIRED!").unwrap();
+        writeln!(context, "ICCID:\t\t\t REQUIRED for ABP!").unwrap();
     }
     writeln!(context, "SEND_FREQUENCY_MS:\t {}", config.send_frequency_ms).unwrap();
+    writeln!(
+        context,
+        "SAMPLE_FREQUENCY_MS:\t {}",
+        config.sample_frequency_ms
+    )
+    .unwrap();
+    writeln!(
+        context,
+        "DIAGNOSTICS_FREQUENCY_MS: {}",
+        config.diagnostics_frequency_ms
+    )
+    .unwrap();
+    if let Some(watchdog_slack_ms) = config.watchdog_slack_ms {
+        writeln!(context, "WATCHDOG_SLACK_MS:\t {}", watchdog_slack_ms).unwrap();
+    } else {
+        writeln!(context, "WATCHDOG_SLACK_MS:\t off").unwrap();
+    }
+    writeln!(context, "MAX_PAYLOAD_LEN:\t {}", config.max_payload_len).unwrap();
     if let Some(network_server_host) = config.network_server_host {
-        writeln!(
-            context,
-            "NETWORK_SERVER_HOST:\t {}.{}.{}.{}",
-            network_server_host[0],
-            network_server_host[1],
-            network_server_host[2],
-            network_server_host[3]
-        )
-        .unwrap();
+        writeln!(context, "NETWORK_SERVER_HOST:\t {}", network_server_host).unwrap();
+    } else if let Some(network_server_name) = &config.network_server_name {
+        writeln!(context, "NETWORK_SERVER_HOST:\t {}", network_server_name).unwrap();
     } else {
         writeln!(context, "NETWORK_SERVER_HOST:\t REQUIRED!").unwrap();
     }
@@ -223,8 +618,113 @@ fn show<'a, T>(
         config.network_server_port
     )
     .unwrap();
+    match config.transport {
+        Transport::Udp => writeln!(context, "TRANSPORT:\t\t udp").unwrap(),
+        Transport::Dtls => writeln!(context, "TRANSPORT:\t\t dtls").unwrap(),
+    };
+    if let Some(psk_identity) = &config.psk_identity {
+        writeln!(context, "PSK_IDENTITY:\t\t {}", psk_identity).unwrap();
+    } else {
+        writeln!(context, "PSK_IDENTITY:\t\t REQUIRED for DTLS!").unwrap();
+    }
+    if let Some(psk) = &config.psk {
+        write!(context, "PSK:\t\t\t 0x").unwrap();
+        for b in psk {
+            write!(context, "{:02X}", b).unwrap();
+        }
+        writeln!(context).unwrap();
+    } else {
+        writeln!(context, "PSK:\t\t\t REQUIRED for DTLS!").unwrap();
+    }
+    writeln!(context, "CONFIRMED:\t\t {}", config.confirmed).unwrap();
+    writeln!(context, "NB_TRANS:\t\t {}", config.nb_trans).unwrap();
+    if let Some(dev_eui) = config.dev_eui {
+        writeln!(context, "DEV_EUI:\t\t 0x{:016X}", dev_eui).unwrap();
+    } else {
+        writeln!(context, "DEV_EUI:\t\t REQUIRED for OTAA!").unwrap();
+    }
+    if let Some(join_eui) = config.join_eui {
+        writeln!(context, "JOIN_EUI:\t\t 0x{:016X}", join_eui).unwrap();
+    } else {
+        writeln!(context, "JOIN_EUI:\t\t REQUIRED for OTAA!").unwrap();
+    }
+    if let Some(app_key) = config.app_key {
+        writeln!(context, "APP_KEY:\t\t 0x{:032X}", app_key).unwrap();
+    } else {
+        writeln!(context, "APP_KEY:\t\t REQUIRED for OTAA!").unwrap();
+    }
+    writeln!(context, "DEV_NONCE:\t\t {}", config.dev_nonce).unwrap();
+    writeln!(context, "REJOIN_AFTER:\t\t {}", config.rejoin_after).unwrap();
+    match config.protocol {
+        Protocol::Raw => writeln!(context, "PROTOCOL:\t\t raw").unwrap(),
+        Protocol::SemtechUdp => writeln!(context, "PROTOCOL:\t\t semtech").unwrap(),
+    };
+    match config.payload_format {
+        PayloadFormat::Environmental => {
+            writeln!(context, "PAYLOAD_FORMAT:\t\t environmental").unwrap()
+        }
+        PayloadFormat::CayenneLpp => writeln!(context, "PAYLOAD_FORMAT:\t\t lpp").unwrap(),
+    };
+    match config.queue_order {
+        QueueOrder::OldestFirst => writeln!(context, "QUEUE_ORDER:\t\t oldest").unwrap(),
+        QueueOrder::NewestFirst => writeln!(context, "QUEUE_ORDER:\t\t newest").unwrap(),
+    };
+    writeln!(context, "SPILL_TO_FLASH:\t\t {}", config.spill_to_flash).unwrap();
+    match config.system_mode {
+        SystemModePolicy::NbIot => writeln!(context, "SYSTEM_MODE:\t\t nbiot").unwrap(),
+        SystemModePolicy::LteM => writeln!(context, "SYSTEM_MODE:\t\t ltem").unwrap(),
+        SystemModePolicy::PreferNbIot { fallback_after } => {
+            writeln!(context, "SYSTEM_MODE:\t\t auto {}", fallback_after).unwrap()
+        }
+    };
+    writeln!(
+        context,
+        "LOW_BATTERY:\t\t below {}mV send {}x less often, below {}mV not at all",
+        config.low_battery.low_mv, config.low_battery.stretch, config.low_battery.cutoff_mv
+    )
+    .unwrap();
+    // What the network has granted is for the modem to say, and so only known
+    // while attached.
+
+    let (granted_psm, granted_edrx) = power::granted();
+    for (name, psm) in [("PSM", config.psm), ("PSM_GRANTED", granted_psm)] {
+        if let Some(psm) = psm {
+            writeln!(
+                context,
+                "{}:\t\t TAU {}s, active {}s",
+                name, psm.periodic_tau_s, psm.active_time_s
+            )
+            .unwrap();
+        } else {
+            writeln!(context, "{}:\t\t off", name).unwrap();
+        }
+    }
+    for (name, edrx) in [("EDRX", config.edrx), ("EDRX_GRANTED", granted_edrx)] {
+        if let Some(edrx) = edrx {
+            writeln!(
+                context,
+                "{}:\t\t cycle {}ms, PTW {}ms",
+                name, edrx.cycle_ms, edrx.ptw_ms
+            )
+            .unwrap();
+        } else {
+            writeln!(context, "{}:\t\t off", name).unwrap();
+        }
+    }
 }
 
+// Our line buffer holds the longest of our commands, being those setting our
+// PSK identity or the network server's name. Lines that overflow it are
+// discarded rather than run truncated.
+
+const PSK_IDENTITY_LINE_LEN: usize = "set-psk-identity ".len() + MAX_PSK_IDENTITY_LEN;
+const NETWORK_HOST_LINE_LEN: usize = "set-network-host ".len() + MAX_HOSTNAME_LEN;
+const LINE_LEN: usize = if PSK_IDENTITY_LINE_LEN > NETWORK_HOST_LINE_LEN {
+    PSK_IDENTITY_LINE_LEN
+} else {
+    NETWORK_HOST_LINE_LEN
+};
+
 pub fn enter<T>(console: Console<T>)
 where
     T: Instance,
@@ -289,12 +789,56 @@ where
                 command: "set-send-freq",
                 help: Some("Sets the data transmission frequency to flash. Defaults to 3600000ms."),
             },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_sample_freq_ms,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "SAMPLE_FREQUENCY_MS",
+                        help: Some("Defaults to 0 i.e. sample when sending"),
+                    }],
+                },
+                command: "set-sample-freq",
+                help: Some("Sets the sensor sampling frequency to flash. Samples taken between transmissions are batched."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_diagnostics_freq_ms,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "DIAGNOSTICS_FREQUENCY_MS",
+                        help: Some("Defaults to 0 i.e. never"),
+                    }],
+                },
+                command: "set-diagnostics-freq",
+                help: Some("Sets how often modem and network diagnostics are sent, on FPort 3."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_watchdog,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "WATCHDOG_SLACK_MS",
+                        help: Some("How long our work may take once woken e.g. 600000, or off."),
+                    }],
+                },
+                command: "set-watchdog",
+                help: Some("Sets how long our work may take once woken before the watchdog resets us."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_max_payload_len,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "MAX_PAYLOAD_LEN",
+                        help: Some("Defaults to 51"),
+                    }],
+                },
+                command: "set-max-payload-len",
+                help: Some("Sets the maximum size of a batch of samples in bytes. Those that don't fit wait for the next transmission."),
+            },
             &Item {
                 item_type: ItemType::Callback {
                     function: set_network_server_host,
                     parameters: &[Parameter::Mandatory {
                         parameter_name: "NETWORK_SERVER_HOST",
-                        help: Some("The IP V4 address of the host"),
+                        help: Some("The IPv4 or IPv6 address, or the name, of the host"),
                     }],
                 },
                 command: "set-network-host",
@@ -311,6 +855,221 @@ where
                 command: "set-network-port",
                 help: Some("Sets the network server port."),
             },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_transport,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "TRANSPORT",
+                        help: Some("Either udp or dtls. Defaults to udp."),
+                    }],
+                },
+                command: "set-transport",
+                help: Some("Sets whether datagrams are sent as plain UDP, or secured with DTLS 1.2 using a pre-shared key."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_psk_identity,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "PSK_IDENTITY",
+                        help: Some("e.g. thingy91-0001"),
+                    }],
+                },
+                command: "set-psk-identity",
+                help: Some("Sets the identity of the DTLS pre-shared key."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_psk,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "PSK",
+                        help: Some("Up to 32 bytes in hex e.g. 2B7E151628AED2A6ABF7158809CF4F3C"),
+                    }],
+                },
+                command: "set-psk",
+                help: Some("Sets the DTLS pre-shared key."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_confirmed,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "CONFIRMED",
+                        help: Some("Either true or false. Defaults to false."),
+                    }],
+                },
+                command: "set-confirmed",
+                help: Some("Sets whether uplinks require acknowledgement from the network server."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_nb_trans,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "NB_TRANS",
+                        help: Some("Defaults to 3."),
+                    }],
+                },
+                command: "set-nb-trans",
+                help: Some("Sets the number of transmissions of an unacknowledged confirmed uplink."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_dev_eui,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "DEV_EUI",
+                        help: Some("e.g. 0004A30B001C0530"),
+                    }],
+                },
+                command: "set-dev-eui",
+                help: Some("Sets a LoRaWAN Device EUI in hex form for over-the-air activation"),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_join_eui,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "JOIN_EUI",
+                        help: Some("e.g. 70B3D57ED0000001"),
+                    }],
+                },
+                command: "set-join-eui",
+                help: Some("Sets a LoRaWAN Join EUI in hex form for over-the-air activation"),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_app_key,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "APP_KEY",
+                        help: Some("e.g. 2B7E151628AED2A6ABF7158809CF4F3C"),
+                    }],
+                },
+                command: "set-app-key",
+                help: Some("Sets a LoRaWAN Application Key in hex form for over-the-air activation"),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_rejoin_after,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "REJOIN_AFTER",
+                        help: Some("Defaults to 0 i.e. never re-join."),
+                    }],
+                },
+                command: "set-rejoin-after",
+                help: Some("Sets the number of consecutive unacknowledged confirmed uplinks, or unanswered link checks, before re-joining."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_protocol,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "PROTOCOL",
+                        help: Some("Either raw or semtech. Defaults to raw."),
+                    }],
+                },
+                command: "set-protocol",
+                help: Some("Sets whether frames are sent as they are, or wrapped for a Semtech UDP packet forwarder bridge."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_payload_format,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "FORMAT",
+                        help: Some("Either environmental or lpp. Defaults to environmental."),
+                    }],
+                },
+                command: "set-payload-format",
+                help: Some("Sets how sensor readings are laid out in uplinks, lpp being Cayenne Low Power Payload."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_queue_order,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "ORDER",
+                        help: Some("Either oldest or newest. Defaults to oldest."),
+                    }],
+                },
+                command: "set-queue-order",
+                help: Some("Sets the order in which uplinks queued while the network is unavailable are sent."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_spill_to_flash,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "SPILL_TO_FLASH",
+                        help: Some("Either true or false. Defaults to false."),
+                    }],
+                },
+                command: "set-spill-to-flash",
+                help: Some("Sets whether queued uplinks that don't fit in memory are written to flash."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_system_mode,
+                    parameters: &[
+                        Parameter::Optional {
+                            parameter_name: "SYSTEM_MODE",
+                            help: Some("Either nbiot, ltem or auto. Defaults to nbiot."),
+                        },
+                        Parameter::Optional {
+                            parameter_name: "FALLBACK_AFTER",
+                            help: Some("For auto, the number of failed attaches before switching. Defaults to 3."),
+                        },
+                    ],
+                },
+                command: "set-system-mode",
+                help: Some("Sets whether to attach with NB-IoT, LTE-M, or NB-IoT falling back to LTE-M."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_psm,
+                    parameters: &[
+                        Parameter::Mandatory {
+                            parameter_name: "PERIODIC_TAU_S",
+                            help: Some("The seconds between tracking area updates e.g. 43200, or off."),
+                        },
+                        Parameter::Optional {
+                            parameter_name: "ACTIVE_TIME_S",
+                            help: Some("The seconds that we remain reachable once idle e.g. 10."),
+                        },
+                    ],
+                },
+                command: "set-psm",
+                help: Some("Sets the Power Saving Mode timers requested of the network, which may grant others."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_edrx,
+                    parameters: &[
+                        Parameter::Mandatory {
+                            parameter_name: "CYCLE_MS",
+                            help: Some("The milliseconds between paging e.g. 81920, or off."),
+                        },
+                        Parameter::Optional {
+                            parameter_name: "PTW_MS",
+                            help: Some("The milliseconds of each paging time window e.g. 5120."),
+                        },
+                    ],
+                },
+                command: "set-edrx",
+                help: Some("Sets the eDRX cycle and paging time window requested of the network, which may grant others."),
+            },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: set_low_battery,
+                    parameters: &[
+                        Parameter::Mandatory {
+                            parameter_name: "LOW_MV",
+                            help: Some("The millivolts below which we send less often e.g. 3600."),
+                        },
+                        Parameter::Mandatory {
+                            parameter_name: "CUTOFF_MV",
+                            help: Some("The millivolts below which we stop sending e.g. 3400."),
+                        },
+                        Parameter::Mandatory {
+                            parameter_name: "STRETCH",
+                            help: Some("How many times less often we send when low e.g. 4."),
+                        },
+                    ],
+                },
+                command: "set-low-battery",
+                help: Some("Sets how we conserve a battery that is running low."),
+            },
             &Item {
                 item_type: ItemType::Callback {
                     function: save,
@@ -327,14 +1086,27 @@ where
                 command: "show",
                 help: Some("Shows settings."),
             },
+            &Item {
+                item_type: ItemType::Callback {
+                    function: show_crash,
+                    parameters: &[],
+                },
+                command: "show-crash",
+                help: Some("Shows the panic that last reset us, until reported by uplink."),
+            },
         ],
         entry: None,
         exit: None,
     };
 
-    let mut buffer = [0u8; 64];
+    let mut buffer = [0u8; LINE_LEN];
     let mut r = Runner::new(&menu, &mut buffer, console);
+    let mut line_len = 0;
+    let mut overflowed = false;
     loop {
+        if let Some(watchdog) = r.context.watchdog.as_mut() {
+            watchdog.feed();
+        }
         let mut rx_buffer = [0u8; 64];
         let rx_buffer = match r
                 .context
@@ -350,10 +1122,32 @@ where
                 b if b as char == '\x1b' => {
                     break;
                 }
-                b if b as char == '\n' => {
+                b'\r' | b'\n' => {
+                    if overflowed {
+                        // Erase the line rather than run what fit
+                        for _ in 0..LINE_LEN {
+                            r.input_byte(b'\x08');
+                        }
+                        writeln!(r.context, "Too long").unwrap();
+                    }
+                    line_len = 0;
+                    overflowed = false;
                     r.input_byte(b'\r');
                 }
-                b => r.input_byte(b),
+                b'\x08' | b'\x7f' => {
+                    if !overflowed {
+                        line_len = line_len.saturating_sub(1);
+                    }
+                    r.input_byte(*b);
+                }
+                b => {
+                    if line_len < LINE_LEN {
+                        line_len += 1;
+                    } else {
+                        overflowed = true;
+                    }
+                    r.input_byte(b);
+                }
             }
         }
     }
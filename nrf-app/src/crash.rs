@@ -0,0 +1,60 @@
+///! Panics are noted in our crash log, which lives in RAM that isn't initialised
+///! as we start, before we reset. When debugging we halt instead, as panic-halt
+///! would.
+use app::crash::{Crash, CrashLog};
+use core::{
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    ptr::addr_of_mut,
+    sync::atomic::{self, Ordering},
+};
+
+#[link_section = ".uninit.CRASH_LOG"]
+static mut CRASH_LOG: MaybeUninit<CrashLog> = MaybeUninit::uninit();
+
+fn crash_log() -> &'static mut CrashLog {
+    // Any contents of the log are valid.
+    unsafe { &mut *(*addr_of_mut!(CRASH_LOG)).as_mut_ptr() }
+}
+
+/// Note that we've started, returning the crash noted before we last reset,
+/// if any.
+pub fn started() -> Option<Crash> {
+    let log = crash_log();
+    log.started();
+    log.crash()
+}
+
+/// Forget our crash, once reported.
+pub fn clear() {
+    crash_log().clear();
+}
+
+/// Note that we're resetting on leaving our console.
+pub fn leaving_console() {
+    crash_log().leaving_console();
+}
+
+/// Whether we last reset on leaving our console.
+pub fn left_console() -> bool {
+    crash_log().left_console()
+}
+
+#[inline(never)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let (file, line) = info
+        .location()
+        .map_or(("", 0), |location| (location.file(), location.line()));
+    crash_log().record(file, line, &info.message());
+
+    if cfg!(debug_assertions) {
+        loop {
+            atomic::compiler_fence(Ordering::SeqCst);
+        }
+    } else {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
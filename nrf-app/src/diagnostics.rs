@@ -0,0 +1,30 @@
+///! Diagnostics are gathered from the modem once attached.
+use app::diagnostics::Diagnostics;
+
+const COMMANDS: [&str; 4] = ["AT+CESQ", "AT+CEREG?", "AT%XMONITOR", "AT%CONEVAL"];
+
+/// Gather what the modem will tell us, along with how long our last attach
+/// took, and whether our requests for power saving were accepted.
+pub fn gather(attach_ms: Option<u32>, power_saving_accepted: Option<bool>) -> Diagnostics {
+    let mut diagnostics = Diagnostics {
+        attach_ms,
+        power_saving_accepted,
+        ..Diagnostics::default()
+    };
+    for command in COMMANDS.iter() {
+        let _ = nrfxlib::at::send_at_command(command, |response| {
+            response.lines().for_each(|line| diagnostics.update(line));
+        });
+    }
+    diagnostics
+}
+
+/// The signal to noise ratio of our connection, in dB, should the modem know
+/// it.
+pub fn snr_db() -> Option<i8> {
+    let mut diagnostics = Diagnostics::default();
+    let _ = nrfxlib::at::send_at_command("AT%XMONITOR", |response| {
+        response.lines().for_each(|line| diagnostics.update(line));
+    });
+    diagnostics.snr_db
+}
@@ -0,0 +1,48 @@
+///! Resolve hostnames using the modem's DNS client.
+use app::host::{Hostname, IpAddr, MAX_HOSTNAME_LEN};
+use nrfxlib_sys as sys;
+
+/// Resolve a hostname to an IPv4 or IPv6 address, whichever the modem's DNS
+/// client returns first, returning `None` if it cannot be.
+pub fn resolve(hostname: &Hostname) -> Option<IpAddr> {
+    let mut node = [0u8; MAX_HOSTNAME_LEN + 1]; // NUL terminated
+    node[..hostname.len()].copy_from_slice(hostname.as_bytes());
+
+    let mut hints: sys::nrf_addrinfo = unsafe { core::mem::zeroed() };
+    hints.ai_family = sys::NRF_AF_UNSPEC as _;
+    hints.ai_socktype = sys::NRF_SOCK_DGRAM as _;
+
+    let mut result: *mut sys::nrf_addrinfo = core::ptr::null_mut();
+    let status = unsafe {
+        sys::nrf_getaddrinfo(
+            node.as_ptr() as *const _,
+            core::ptr::null(),
+            &hints,
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+
+    // Addresses are in network byte order, which is the order of their octets.
+    let mut addr = None;
+    let mut info = result;
+    while addr.is_none() && !info.is_null() {
+        unsafe {
+            let i = &*info;
+            if !i.ai_addr.is_null() {
+                if i.ai_family == sys::NRF_AF_INET as _ {
+                    let sockaddr = &*(i.ai_addr as *const sys::nrf_sockaddr_in);
+                    addr = Some(IpAddr::V4(sockaddr.sin_addr.s_addr.to_ne_bytes()));
+                } else if i.ai_family == sys::NRF_AF_INET6 as _ {
+                    let sockaddr = &*(i.ai_addr as *const sys::nrf_sockaddr_in6);
+                    addr = Some(IpAddr::V6(sockaddr.sin6_addr.s6_addr));
+                }
+            }
+            info = i.ai_next;
+        }
+    }
+    unsafe { sys::nrf_freeaddrinfo(result) };
+    addr
+}
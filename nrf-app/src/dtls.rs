@@ -0,0 +1,37 @@
+///! DTLS credentials are held by the modem under a security tag, to which our
+///! sockets then refer. The modem only accepts credentials while it is offline,
+///! so we provision them before first attaching.
+use app::config::{Psk, PskIdentity};
+use core::fmt::Write;
+use heapless::String;
+
+/// The security tag under which our credentials are held.
+pub const SEC_TAG: u32 = 0x4c57_0001;
+
+// Credential types of AT%CMNG
+
+const PSK: u8 = 3;
+const PSK_IDENTITY: u8 = 4;
+
+/// Write our pre-shared key and its identity to the modem, returning whether
+/// it accepted both.
+pub fn provision(identity: &PskIdentity, psk: &Psk) -> bool {
+    let mut psk_hex: String<64> = String::new();
+    for b in psk {
+        if write!(psk_hex, "{:02x}", b).is_err() {
+            return false;
+        }
+    }
+    write_credential(PSK, &psk_hex) && write_credential(PSK_IDENTITY, identity)
+}
+
+fn write_credential(credential_type: u8, content: &str) -> bool {
+    let mut command: String<128> = String::new();
+    write!(
+        command,
+        "AT%CMNG=0,{},{},\"{}\"",
+        SEC_TAG, credential_type, content
+    )
+    .is_ok()
+        && nrfxlib::at::send_at_command(&command, |_| ()).is_ok()
+}
@@ -0,0 +1,118 @@
+///! The link to our network server. LoRaWAN frames are either sent as they are,
+///! or wrapped for a Semtech packet forwarder bridge, in which case we also keep
+///! a path open for downlinks, and unwrap them as they arrive.
+use app::{
+    config::Protocol,
+    gwmp::{self, Packet, RxInfo},
+};
+use bsp::hal::Delay;
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::socket::UdpSocket;
+
+// Replies are polled for this often during a receive window.
+
+const RX_POLL_MS: u32 = 100;
+
+pub struct Link {
+    udp_socket: UdpSocket,
+    protocol: Protocol,
+    gateway_eui: u64,
+    token: u16,
+    failed: bool,
+}
+
+impl Link {
+    pub fn new(udp_socket: UdpSocket, protocol: Protocol, gateway_eui: u64) -> Self {
+        Link {
+            udp_socket,
+            protocol,
+            gateway_eui,
+            token: 0,
+            failed: false,
+        }
+    }
+
+    /// Whether a write to our socket has failed, in which case the link
+    /// should be re-created.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    fn write(&mut self, datagram: &[u8]) -> bool {
+        let written = self.udp_socket.write(datagram);
+        self.failed |= !written;
+        written
+    }
+
+    fn next_token(&mut self) -> u16 {
+        self.token = self.token.wrapping_add(1);
+        self.token
+    }
+
+    /// Send a frame, returning whether it was handed to the modem. We can't
+    /// know whether it then reached the network server; everything is
+    /// best-effort in IoT. Not being able to hand it over though means that
+    /// the network is unavailable.
+    pub fn send(&mut self, frame: &[u8]) -> bool {
+        match self.protocol {
+            Protocol::Raw => self.write(frame),
+            Protocol::SemtechUdp => {
+                // We don't keep time, so our timestamp is of no consequence.
+                let token = self.next_token();
+                match gwmp::push_data(token, self.gateway_eui, frame, 0, &RxInfo::default()) {
+                    Some(datagram) => self.write(&datagram),
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// Tell a packet forwarder bridge where to send downlinks. As we sleep
+    /// between uplinks, we do this before each one rather than periodically.
+    pub fn keep_alive(&mut self) {
+        if self.protocol == Protocol::SemtechUdp {
+            let token = self.next_token();
+            self.write(&gwmp::pull_data(token, self.gateway_eui));
+        }
+    }
+
+    /// Listen for a reply after an uplink, in the style of a LoRaWAN class-A
+    /// device. We poll the socket for a window long enough to cover a network
+    /// server's RX1 and RX2 delays, returning the first reply that decodes. RX2
+    /// follows RX1 by a second, and we allow a further second for the network.
+    pub fn receive<T, F>(&mut self, delayer: &mut Delay, rx_delay_s: u8, mut decode: F) -> Option<T>
+    where
+        F: FnMut(&mut [u8]) -> Option<T>,
+    {
+        let mut rx_buffer = [0u8; gwmp::MAX_DATAGRAM_LEN];
+        let rx_window_ms = (u32::from(rx_delay_s) + 2) * 1000;
+        for _ in 0..rx_window_ms / RX_POLL_MS {
+            if let Some(n) = self.udp_socket.recv(&mut rx_buffer) {
+                if let Some(reply) = self.unwrap(&mut rx_buffer[..n], &mut decode) {
+                    return Some(reply);
+                }
+            }
+            delayer.delay_ms(RX_POLL_MS);
+        }
+        None
+    }
+
+    fn unwrap<T, F>(&mut self, bytes: &mut [u8], decode: &mut F) -> Option<T>
+    where
+        F: FnMut(&mut [u8]) -> Option<T>,
+    {
+        match self.protocol {
+            Protocol::Raw => decode(bytes),
+            Protocol::SemtechUdp => match gwmp::parse(bytes)? {
+                Packet::PullResp { token, json } => {
+                    let mut phy_payload = [0u8; 256];
+                    let len = gwmp::txpk_data(json, &mut phy_payload)?;
+                    self.write(&gwmp::tx_ack(token, self.gateway_eui));
+                    decode(&mut phy_payload[..len])
+                }
+                _ => None,
+            },
+        }
+    }
+}
@@ -4,7 +4,28 @@
 extern crate thingy_91_nrf9160_bsp as bsp;
 extern crate tinyrlibc;
 
-use app::{data_up_unconfirmed, nwk_addr, EnvironmentalPayload};
+use app::{
+    batch::{encode_battery, Samples, BATCH_F_PORT, BATTERY_LEN},
+    battery::BatteryVoltage,
+    codec::{EnvironmentalCodec, Payload, PayloadCodec},
+    config::{Config, PayloadFormat, Transport},
+    connectivity::{Action, BackoffPolicy, Connectivity, Event},
+    crash::CrashCodec,
+    data_up_confirmed, data_up_unconfirmed,
+    diagnostics::{DiagnosticsCodec, DIAGNOSTICS_F_PORT},
+    downlink::{decode_downlink, Downlink, DownlinkCounter},
+    fcnt::{CounterLog, Counters, FrameCounter},
+    host::Resolver,
+    join::{decode_join_accept, join_request, Rejoin, Session, JOIN_ACCEPT_DELAY1_S},
+    lpp::LppCodec,
+    mac::{MacCommands, UplinkMacCommand},
+    nwk_addr,
+    queue::{Uplink, UplinkQueue},
+    reset::{watchdog_ticks, ResetCodec, ResetReason, RESET_F_PORT},
+    retransmission::{Outcome, Retransmission, RetransmissionPolicy},
+    system_mode::{SystemMode, SystemModeSelector},
+    EnvironmentalPayload, READING_F_PORT,
+};
 use bme680::{Bme680, I2CAddress, IIRFilterSize, OversamplingSetting, PowerMode, SettingsBuilder};
 use bsp::{
     hal::{clocks, pwm, rtc, twim, Delay, Timer, Twim},
@@ -12,32 +33,29 @@ use bsp::{
     prelude::U32Ext,
     Board,
 };
-use config::Config;
 use core::{
     cell::RefCell,
-    fmt::Write,
+    convert::TryFrom,
     sync::atomic::{AtomicBool, Ordering},
     time::Duration,
 };
 use cortex_m::{asm, interrupt::Mutex};
 use cortex_m_rt::entry;
-use embedded_hal::Pwm;
-use heapless::String;
+use embedded_hal::{blocking::delay::DelayMs, Pwm};
 use nrf_hal_common::nvmc::Nvmc;
-use nrfxlib::udp::UdpSocket;
-
-// pick a panicking behavior
-#[cfg(debug_assertions)]
-use panic_halt as _;
-
-// release profile: minimize the binary size of the application
-#[cfg(not(debug_assertions))]
-use panic_reset as _;
 
-use crate::command::Console;
+use crate::{command::Console, link::Link, socket::UdpSocket, spill::SpillLog, watchdog::Watchdog};
 
 pub mod command;
-pub mod config;
+pub mod crash;
+pub mod diagnostics;
+pub mod dns;
+pub mod dtls;
+pub mod link;
+pub mod power;
+pub mod socket;
+pub mod spill;
+pub mod watchdog;
 
 // Interrupt handlers for LTE related hardware. Defers straight to the library.
 
@@ -76,6 +94,22 @@ fn RTC0() {
     TIMER_EXPIRED.store(true, Ordering::Relaxed);
 }
 
+// Our RTC ticks every 125ms, the most that its prescaler permits.
+
+const RTC_PRESCALER: u32 = 0xFFF;
+const RTC_TICK_MS: u32 = 1000 / (clocks::LFCLK_FREQ / (RTC_PRESCALER + 1));
+
+// The time since we last woke.
+
+fn since_wake_ms() -> u32 {
+    cortex_m::interrupt::free(|cs| {
+        RTC.borrow(cs)
+            .borrow()
+            .as_ref()
+            .map_or(0, |rtc| rtc.get_counter() * RTC_TICK_MS)
+    })
+}
+
 // Setup required for the modem
 
 fn init_modem(nvic: &mut NVIC) {
@@ -99,61 +133,310 @@ fn init_modem(nvic: &mut NVIC) {
     nrfxlib::init().unwrap();
 }
 
-// Flash storage that we use for configuration
+// Listen for a downlink after an uplink.
+
+fn receive_downlink(
+    link: &mut Link,
+    delayer: &mut Delay,
+    rx_delay_s: u8,
+    session: &Session,
+    fcnt_down: &mut DownlinkCounter,
+) -> Option<Downlink> {
+    link.receive(delayer, rx_delay_s, |bytes| {
+        decode_downlink(
+            bytes,
+            session.dev_addr,
+            session.nwk_skey,
+            session.app_skey,
+            fcnt_down,
+        )
+        .ok()
+    })
+}
+
+// Join the network over the air. DevNonces are reserved ahead of their use,
+// with each reservation persisted before it is used, so that a DevNonce is
+// never repeated, even across resets. Once they're used up, we no longer join.
+// We try a few times, backing off between attempts, and otherwise try again
+// when we next wake. We also stop should the link fail.
+
+const JOIN_ATTEMPTS: u8 = 3;
+const JOIN_BACKOFF_MS: u32 = 10_000;
+
+fn join(
+    link: &mut Link,
+    delayer: &mut Delay,
+    config: &Config,
+    dev_nonces: &mut FrameCounter,
+    mut persist: impl FnMut(u32) -> bool,
+) -> Option<Session> {
+    let dev_eui = config.dev_eui?;
+    let join_eui = config.join_eui?;
+    let app_key = config.app_key?;
+    for attempt in 0..JOIN_ATTEMPTS {
+        if attempt > 0 {
+            delayer.delay_ms(JOIN_BACKOFF_MS);
+        }
+        let dev_nonce = u16::try_from(dev_nonces.value()).ok()?;
+        if dev_nonces.reserve().map_or(true, &mut persist) {
+            dev_nonces.increment();
+            link.keep_alive();
+            if !link.send(&join_request(dev_eui, join_eui, dev_nonce, app_key)) {
+                break;
+            }
+            if let Some(session) = link.receive(delayer, JOIN_ACCEPT_DELAY1_S, |bytes| {
+                decode_join_accept(bytes, dev_nonce, app_key).ok()
+            }) {
+                return Some(session);
+            }
+        } else {
+            // Our reservation wasn't persisted, so we'll reserve again
+            *dev_nonces = FrameCounter::restore(Some(dev_nonces.value()), DEV_NONCE_RESERVATION);
+        }
+    }
+    None
+}
+
+// Open a socket to the network server, resolving its address if we're
+// configured with its name.
+
+fn open_link(config: &Config, resolver: &mut Resolver) -> Option<Link> {
+    let addr = match &config.network_server_name {
+        Some(name) => resolver.address(|| dns::resolve(name))?,
+        None => config.network_server_host?,
+    };
+    let sec_tag = match config.transport {
+        Transport::Udp => None,
+        Transport::Dtls => Some(dtls::SEC_TAG),
+    };
+    let udp_socket = UdpSocket::connect(&addr, config.network_server_port, sec_tag)?;
+    Some(Link::new(udp_socket, config.protocol, config.gateway_eui()))
+}
+
+// How our last attach went, as reported with our diagnostics, along with the
+// signal to noise ratio of the connection it gave us.
+
+#[derive(Clone, Copy)]
+struct Attach {
+    ms: Option<u32>,
+    power_saving_accepted: bool,
+    snr_db: Option<i8>,
+}
+
+// Carry out the actions of our connectivity state machine, reporting their
+// outcomes, until there's nothing more to do. Returns how attaching went,
+// should we have attached.
+
+fn connect(
+    connectivity: &mut Connectivity,
+    mut action: Action,
+    link: &mut Option<Link>,
+    config: &Config,
+    resolver: &mut Resolver,
+    system_mode: &mut SystemModeSelector,
+    now_ms: u64,
+) -> Option<Attach> {
+    let mut attach = None;
+    loop {
+        action = match action {
+            Action::None => break,
+            Action::Attach => {
+                let mode = match system_mode.mode() {
+                    SystemMode::NbIot => nrfxlib::modem::SystemMode::NbIot,
+                    SystemMode::LteM => nrfxlib::modem::SystemMode::LteM,
+                };
+                let started_ms = since_wake_ms();
+                let mut power_saving_accepted = false;
+                let attached = nrfxlib::modem::set_system_mode(mode)
+                    .and_then(|_| {
+                        power_saving_accepted = power::request(
+                            system_mode.mode(),
+                            config.psm.as_ref(),
+                            config.edrx.as_ref(),
+                        );
+                        nrfxlib::modem::on()
+                    })
+                    .and_then(|_| nrfxlib::modem::wait_for_lte())
+                    .is_ok();
+                let event = if attached {
+                    // Our RTC is cleared should we wake while attaching.
+                    attach = Some(Attach {
+                        ms: since_wake_ms().checked_sub(started_ms),
+                        power_saving_accepted,
+                        snr_db: diagnostics::snr_db(),
+                    });
+                    system_mode.attached();
+                    Event::Attached
+                } else {
+                    system_mode.attach_failed();
+                    Event::AttachFailed
+                };
+                connectivity.handle(event, now_ms)
+            }
+            Action::OpenSocket => {
+                *link = open_link(config, resolver);
+                if link.is_some() {
+                    Action::None
+                } else {
+                    resolver.link_failed();
+                    connectivity.handle(Event::LinkFailed, now_ms)
+                }
+            }
+            Action::PowerOff => {
+                *link = None;
+                let _ = nrfxlib::modem::off();
+                Action::None
+            }
+        }
+    }
+    attach
+}
+
+// Measure our battery voltage via the modem.
+
+fn measure_battery() -> Option<BatteryVoltage> {
+    let mut battery = None;
+    let _ = nrfxlib::at::send_at_command("AT%XVBAT", |response| {
+        battery = response.lines().find_map(BatteryVoltage::parse);
+    });
+    battery
+}
+
+// Application specific handling of downlinks. MAC commands are handled before
+// we get here. We don't currently define any commands for the application's
+// FPort, so this is where they'd be handled.
+
+fn on_downlink(_downlink: &Downlink) {}
+
+// Uplink frame counters are reserved this many at a time, limiting how often
+// we write them to flash.
+
+const FCNT_RESERVATION: u32 = 16;
+
+// DevNonces are reserved this many at a time, as with uplink frame counters.
+
+const DEV_NONCE_RESERVATION: u32 = 16;
+
+// The network server's name is resolved again after this many consecutive
+// failures to reach it.
+
+const RESOLVE_AFTER_FAILURES: u8 = 3;
+
+// The number of uplinks retained in memory while the network is unavailable.
+
+const QUEUE_LEN: usize = 8;
+
+// Flash storage that we use for configuration, frame counters and spilled uplinks
 extern "C" {
     #[link_name = "_config"]
-    static mut CONFIG: [u32; 1024];
+    static mut CONFIG: [u32; 5120];
 }
 
 #[entry]
 fn main() -> ! {
-    // Initialize device
+    // Initialize device, noting why we last reset, and how should we have
+    // panicked. The soft reset on leaving our console is no panic.
 
+    let crash = crash::started();
+    let left_console = crash::left_console();
     let mut board = Board::take().unwrap();
+    let reset_reason = match watchdog::reset_reason() {
+        ResetReason::Panic if left_console => ResetReason::Console,
+        reason => reason,
+    };
 
     let mut nvmc = Nvmc::new(board.NVMC_NS, unsafe { &mut CONFIG });
-    let mut config = Config::load(&mut nvmc).ok().unwrap();
+    // Should our configuration be unrecognised, we start afresh and so enter
+    // the console. What's in flash remains until saved over.
+
+    let mut config = Config::load(&mut nvmc).unwrap_or_default();
+
+    // Should our frame counters not load, we carry on without them, saving
+    // them afresh. Should our spilled uplinks not load, we spill no more
+    // until they do.
+
+    let (mut counter_log, counters) =
+        CounterLog::load(&mut nvmc).unwrap_or_else(|_| (CounterLog::unloaded(), None));
+    let mut spill_log = SpillLog::load(&mut nvmc).unwrap_or_else(|_| SpillLog::unloaded());
+
+    // The modem is initialised ahead of the console, which asks it what the
+    // network has granted. Should our watchdog have survived a soft reset, as
+    // it does after we panic, the console feeds it.
+
+    init_modem(&mut board.NVIC);
 
     if !config.is_complete() || board.buttons.button_1.is_active() {
         let mut timer = Timer::new(board.TIMER0_NS);
         let mut uarte = board.cdc_uart;
-        let console = Console::with(&mut config, &mut nvmc, &mut timer, &mut uarte);
+        let console = Console::with(
+            &mut config,
+            &mut nvmc,
+            &mut timer,
+            &mut uarte,
+            crash.as_ref(),
+            Watchdog::recover(board.WDT_NS),
+        );
         command::enter(console);
+        crash::leaving_console();
         cortex_m::peripheral::SCB::sys_reset();
     }
 
-    // Initialise our network connectivity
+    // Start our watchdog, which we feed each time we've woken and done our
+    // work without our link failing, so that we're reset should we hang, or
+    // our link keep failing. Should it not start, we run without it.
 
-    init_modem(&mut board.NVIC);
+    let wake_ms = config.wake_frequency_ms();
+    let mut watchdog = config
+        .watchdog_slack_ms
+        .and_then(|slack_ms| Watchdog::start(board.WDT_NS, watchdog_ticks(wake_ms, slack_ms)));
 
-    nrfxlib::modem::set_system_mode(nrfxlib::modem::SystemMode::NbIot).unwrap();
+    // Initialise our network connectivity. We attach when we first have
+    // something to send, and should that fail, we try again later.
 
-    nrfxlib::modem::on().unwrap();
+    let mut system_mode = SystemModeSelector::new(config.system_mode);
 
-    nrfxlib::modem::wait_for_lte().unwrap();
-
-    let udp_socket = UdpSocket::new().unwrap();
-    let mut network_server_host: String<15> = String::new();
-    {
-        let ipv4_addr = config.network_server_host.unwrap();
-        write!(
-            &mut network_server_host,
-            "{}.{}.{}.{}",
-            ipv4_addr[0], ipv4_addr[1], ipv4_addr[2], ipv4_addr[3],
-        )
-        .unwrap();
+    if config.transport == Transport::Dtls {
+        let (identity, psk) = config.psk_credentials().unwrap();
+        dtls::provision(identity, psk);
     }
-    udp_socket
-        .connect(&network_server_host, config.network_server_port)
-        .unwrap();
 
-    // Setup LoRaWAN info
+    let mut connectivity = Connectivity::new(BackoffPolicy::default());
+    let mut resolver = Resolver::with_last(RESOLVE_AFTER_FAILURES, config.resolved_host);
+    let mut link: Option<Link> = None;
+
+    let mut delayer = Delay::new(board.SYST);
+
+    // Setup LoRaWAN info. When activated by personalisation, we resume our
+    // frame counters from flash. Otherwise we join the network once attached,
+    // which establishes a new session and so our frame counters start afresh.
+
+    let (mut session, mut fcnt, mut fcnt_down) = if config.is_otaa() {
+        (
+            None,
+            FrameCounter::restore(None, FCNT_RESERVATION),
+            DownlinkCounter::new(),
+        )
+    } else {
+        let dev_eui = config.iccid.unwrap();
+        (
+            Some(Session {
+                dev_addr: nwk_addr(dev_eui, config.net_id),
+                nwk_skey: config.nwkskey.unwrap(),
+                app_skey: config.appskey.unwrap(),
+            }),
+            FrameCounter::restore(counters.map(|c| c.fcnt_up), FCNT_RESERVATION),
+            DownlinkCounter::with_last(counters.and_then(|c| c.fcnt_down)),
+        )
+    };
 
-    let dev_eui = config.iccid.unwrap();
-    let dev_addr = nwk_addr(dev_eui, config.net_id);
+    // DevNonces resume from their reservation, or from that configured should
+    // it be greater.
 
-    let nwk_skey = config.nwkskey.unwrap();
-    let app_skey = config.appskey.unwrap();
+    let dev_nonce = counters.map_or(0, |c| c.dev_nonce);
+    let mut dev_nonces = FrameCounter::restore(
+        Some(dev_nonce.max(u32::from(config.dev_nonce))),
+        DEV_NONCE_RESERVATION,
+    );
 
     // Setup the environmental sensor
 
@@ -164,8 +447,6 @@ fn main() -> ! {
 
     let i2c = Twim::new(board.TWIM2_NS, pins, twim::Frequency::K400);
 
-    let mut delayer = Delay::new(board.SYST);
-
     let mut dev = Bme680::init(i2c, &mut delayer, I2CAddress::Primary).unwrap();
     let settings = SettingsBuilder::new()
         .with_humidity_oversampling(OversamplingSetting::OS2x)
@@ -181,7 +462,8 @@ fn main() -> ! {
 
     // Our main loop where we read our sensors, send data and then sleep
 
-    let mut fcnt = 0; // frame counter for LoRaWAN
+    let mut mac = MacCommands::new(); // MAC commands to and from the network server
+    let mut rejoin = Rejoin::new(config.rejoin_after);
 
     // Set up our LED
 
@@ -192,15 +474,33 @@ fn main() -> ! {
     // Enable the low-frequency-clock which is required by the RTC
     clocks::Clocks::new(board.CLOCK_NS).start_lfclk();
 
-    // Setup our timer so we can wake up to do our work periodically
+    // Setup our timer so we can wake up to do our work periodically. We keep
+    // time by counting our wakes.
+
+    let mut clock_ms = 0_u64;
+    let mut since_send_ms = config.send_frequency_ms; // Send when we first wake
+    let mut since_diagnostics_ms = config.diagnostics_frequency_ms; // Send when we first attach
+    let mut attach = None;
+    let mut samples = Samples::new();
+    let mut queue: UplinkQueue<QUEUE_LEN> = UplinkQueue::new(config.queue_order.into());
+
+    // Report why we last reset along with our first reading, followed by our
+    // crash should we have panicked, which we forget once sent.
 
-    let prescaler = 0xFFF; // Max resolution of 125ms per tick
-    let mut rtc = rtc::Rtc::new(board.RTC0_NS, prescaler).unwrap();
-    rtc.set_compare(
-        rtc::RtcCompareReg::Compare0,
-        config.send_frequency_ms / (1000 / (clocks::LFCLK_FREQ / (prescaler + 1))),
-    )
-    .unwrap();
+    let mut payload = Payload::new();
+    let _ = ResetCodec.encode(&reset_reason, &mut payload);
+    let mut crash_unsent = crash.is_some();
+    if let Some(crash) = crash {
+        let _ = CrashCodec.encode(&crash, &mut payload);
+    }
+    let _ = queue.push(Uplink {
+        f_port: RESET_F_PORT,
+        payload,
+    });
+
+    let mut rtc = rtc::Rtc::new(board.RTC0_NS, RTC_PRESCALER).unwrap();
+    rtc.set_compare(rtc::RtcCompareReg::Compare0, wake_ms / RTC_TICK_MS)
+        .unwrap();
     rtc.enable_event(rtc::RtcInterrupt::Compare0);
     rtc.enable_interrupt(rtc::RtcInterrupt::Compare0, Some(&mut board.NVIC));
     rtc.enable_counter();
@@ -228,28 +528,358 @@ fn main() -> ! {
 
             let (data, _) = dev.get_sensor_data(&mut delayer).unwrap();
 
-            // Construct a LoRaWAN packet from the data.
+            // Measure our battery, which determines how often we send, if at all.
+
+            let battery = measure_battery();
+            if let Some(battery) = battery {
+                mac.set_battery(battery.level());
+            }
+            // We send less often should the network server restrict our duty
+            // cycle.
 
-            let payload = EnvironmentalPayload {
+            let send_frequency_ms = config
+                .low_battery
+                .send_frequency_ms(config.send_frequency_ms, battery)
+                .map(|ms| mac.throttle_ms(ms));
+            let send_due = send_frequency_ms.map_or(false, |ms| since_send_ms >= ms);
+
+            // Construct a payload from the data, either on its own, or batched
+            // along with those taken since our last uplink, in which case we
+            // only send once it is time to. Our battery voltage follows, in
+            // our payload format or as the batch's trailer.
+
+            let reading = EnvironmentalPayload {
                 temperature: unsafe { (data.temperature_celsius() * 100f32).to_int_unchecked() },
                 pressure: unsafe { (data.pressure_hpa() * 100f32).to_int_unchecked() },
                 humidity: unsafe { (data.humidity_percent() * 1000f32).to_int_unchecked() },
                 gas_resistance: data.gas_resistance_ohm(),
             };
+            let mut payload = Payload::new();
+            let f_port = if config.is_batching() {
+                let now_s = (clock_ms / 1000) as u32;
+                samples.push(now_s, reading);
+                if send_due || (samples.is_full() && send_frequency_ms.is_some()) {
+                    since_send_ms = 0;
+                    let battery_len = if battery.is_some() { BATTERY_LEN } else { 0 };
+                    let max_len = usize::from(config.max_payload_len).saturating_sub(battery_len);
+                    if samples.take(now_s, max_len, &mut payload) > 0 {
+                        if let Some(battery) = battery {
+                            let _ = encode_battery(&battery, &mut payload);
+                        }
+                        Some(BATCH_F_PORT)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            } else if send_due {
+                since_send_ms = 0;
+                let (codec, battery_codec): (
+                    &dyn PayloadCodec<EnvironmentalPayload>,
+                    &dyn PayloadCodec<BatteryVoltage>,
+                ) = match config.payload_format {
+                    PayloadFormat::Environmental => (&EnvironmentalCodec, &EnvironmentalCodec),
+                    PayloadFormat::CayenneLpp => (&LppCodec, &LppCodec),
+                };
+                let _ = codec.encode(&reading, &mut payload);
+                if let Some(battery) = battery {
+                    let _ = battery_codec.encode(&battery, &mut payload);
+                }
+                Some(READING_F_PORT)
+            } else {
+                None
+            };
 
-            let payload_bytes = data_up_unconfirmed(dev_addr, fcnt, &payload, nwk_skey, app_skey);
-
-            // Send the data. There's nothing we can do about transmissions failing.
-            // Everything is best-effort in IoT.
-
-            let _ = udp_socket.write(&payload_bytes);
-
-            fcnt += 1;
-
-            // All done. Time to sleep.
+            // Queue the uplink, spilling the oldest to flash if there's no room.
+
+            if let Some(f_port) = f_port {
+                if let Some(evicted) = queue.push(Uplink { f_port, payload }) {
+                    if config.spill_to_flash {
+                        let _ = spill_log.store(&mut nvmc, &evicted);
+                    }
+                }
+            }
+
+            // Connect, unless we're backing off from having failed to, or our
+            // battery is too low to send. Once connected, and joined if need
+            // be, send what we have queued until the link fails, in which case
+            // the uplink is retained for next time. Spilled uplinks return to
+            // the queue as room allows.
+
+            let event = if send_frequency_ms.is_some() {
+                Event::Connect
+            } else {
+                Event::Disconnect
+            };
+            let action = connectivity.handle(event, clock_ms);
+            attach = connect(
+                &mut connectivity,
+                action,
+                &mut link,
+                &config,
+                &mut resolver,
+                &mut system_mode,
+                clock_ms,
+            )
+            .or(attach);
+
+            // Our demodulation margin, should the network server ask for it,
+            // is the signal to noise ratio of our connection as last attached.
+
+            if let Some(snr_db) = attach.and_then(|attach| attach.snr_db) {
+                mac.set_margin(snr_db);
+            }
+
+            // Send our diagnostics every so often, which requires that we're
+            // attached.
+
+            if config.diagnostics_frequency_ms > 0
+                && since_diagnostics_ms >= config.diagnostics_frequency_ms
+                && connectivity.is_attached()
+            {
+                let mut payload = Payload::new();
+                let gathered = diagnostics::gather(
+                    attach.and_then(|attach| attach.ms),
+                    attach.map(|attach| attach.power_saving_accepted),
+                );
+                let _ = DiagnosticsCodec.encode(&gathered, &mut payload);
+                let uplink = Uplink {
+                    f_port: DIAGNOSTICS_F_PORT,
+                    payload,
+                };
+                if let Some(evicted) = queue.push(uplink) {
+                    if config.spill_to_flash {
+                        let _ = spill_log.store(&mut nvmc, &evicted);
+                    }
+                }
+                since_diagnostics_ms = 0;
+            }
+
+            let mut outcome = None;
+            if let Some(link) = link.as_mut() {
+                loop {
+                    let current = match session {
+                        Some(current) => current,
+                        None => {
+                            let persist = |dev_nonce| {
+                                let counters = Counters {
+                                    fcnt_up: fcnt.reserved(),
+                                    fcnt_down: fcnt_down.last(),
+                                    dev_nonce,
+                                };
+                                counter_log.save(&mut nvmc, &counters).is_ok()
+                            };
+                            match join(link, &mut delayer, &config, &mut dev_nonces, persist) {
+                                Some(joined) => {
+                                    session = Some(joined);
+                                    fcnt = FrameCounter::restore(None, FCNT_RESERVATION);
+                                    fcnt_down = DownlinkCounter::new();
+                                    mac.rejoined();
+                                    joined
+                                }
+                                None => break,
+                            }
+                        }
+                    };
+
+                    if config.spill_to_flash {
+                        while !queue.is_full() {
+                            match spill_log.take(&mut nvmc) {
+                                Some(spilled) => {
+                                    let _ = queue.unspill(spilled);
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                    let uplink = match queue.pop() {
+                        Some(uplink) => uplink,
+                        None => break,
+                    };
+
+                    // Persist our frame counters if we're about to use up our reservation.
+                    // The downlink counter is persisted along with it, as it also is
+                    // whenever it advances. If we can't write to flash then we carry on
+                    // regardless, rather than stop sending.
+
+                    if let Some(fcnt_up) = fcnt.reserve() {
+                        let _ = counter_log.save(
+                            &mut nvmc,
+                            &Counters {
+                                fcnt_up,
+                                fcnt_down: fcnt_down.last(),
+                                dev_nonce: dev_nonces.reserved(),
+                            },
+                        );
+                    }
+
+                    // Send the data. Confirmed uplinks are retransmitted with the same
+                    // frame counter until acknowledged, or we give up. After each
+                    // transmission we listen for a downlink.
+
+                    let (payload_bytes, nb_trans) = if config.confirmed {
+                        (
+                            data_up_confirmed(
+                                &current,
+                                fcnt.value(),
+                                uplink.f_port,
+                                &uplink.payload,
+                                mac.uplink_commands(),
+                            ),
+                            config.nb_trans,
+                        )
+                    } else {
+                        (
+                            data_up_unconfirmed(
+                                &current,
+                                fcnt.value(),
+                                uplink.f_port,
+                                &uplink.payload,
+                                mac.uplink_commands(),
+                            ),
+                            1,
+                        )
+                    };
+                    let link_checking = mac
+                        .uplink_commands()
+                        .contains(&UplinkMacCommand::LinkCheckReq);
+                    link.keep_alive();
+                    let mut sent = false;
+                    let mut answered = false;
+                    let mut retransmission =
+                        Retransmission::new(RetransmissionPolicy::new(nb_trans, 2000));
+                    while let Some(delay_ms) = retransmission.next_attempt() {
+                        if delay_ms > 0 {
+                            delayer.delay_ms(delay_ms);
+                        }
+                        if !link.send(&payload_bytes) {
+                            break;
+                        }
+                        if !sent {
+                            mac.uplink_sent();
+                            sent = true;
+                        }
+                        if let Some(downlink) = receive_downlink(
+                            link,
+                            &mut delayer,
+                            mac.rx_delay_s(),
+                            &current,
+                            &mut fcnt_down,
+                        ) {
+                            // Persist our downlink counter now that it has
+                            // advanced, so that the downlink isn't accepted again
+                            // should we reset.
+
+                            let _ = counter_log.save(
+                                &mut nvmc,
+                                &Counters {
+                                    fcnt_up: fcnt.reserved(),
+                                    fcnt_down: fcnt_down.last(),
+                                    dev_nonce: dev_nonces.reserved(),
+                                },
+                            );
+                            if downlink.ack {
+                                retransmission.ack();
+                            }
+                            answered = true;
+                            mac.handle_downlink(&downlink);
+                            on_downlink(&downlink);
+                        }
+                    }
+
+                    if !sent {
+                        if let Some(evicted) = queue.restore(uplink) {
+                            if config.spill_to_flash {
+                                let _ = spill_log.store(&mut nvmc, &evicted);
+                            }
+                        }
+                        break;
+                    }
+
+                    fcnt.increment();
+                    outcome = Some(Event::Sent);
+
+                    if crash_unsent && uplink.f_port == RESET_F_PORT {
+                        crash::clear();
+                        crash_unsent = false;
+                    }
+
+                    // Re-join if the network appears to have forgotten us, which we
+                    // do before sending anything further. Confirmed uplinks tell us
+                    // as much by going unacknowledged. Unconfirmed uplinks needn't
+                    // be answered, so should one not be, we check our link with the
+                    // next, and tell by whether that check is answered.
+
+                    if config.is_otaa() && config.rejoin_after > 0 {
+                        let heard = if config.confirmed {
+                            Some(retransmission.outcome() == Outcome::Acked)
+                        } else if answered {
+                            Some(true)
+                        } else {
+                            mac.request(UplinkMacCommand::LinkCheckReq);
+                            if link_checking {
+                                Some(false)
+                            } else {
+                                None
+                            }
+                        };
+                        if heard.map_or(false, |heard| rejoin.uplink(heard)) {
+                            session = None;
+                        }
+                    }
+                }
+                if link.failed() {
+                    outcome = Some(Event::LinkFailed);
+                }
+            }
+            if let Some(event) = outcome {
+                match event {
+                    Event::LinkFailed => resolver.link_failed(),
+                    _ => {
+                        resolver.sent();
+
+                        // Remember the address resolved for the network server's
+                        // name once we've sent to it, so that we may fall back on
+                        // it after a reset. Addresses that we fail to send to
+                        // aren't saved, nor those that are already.
+
+                        if config.network_server_name.is_some()
+                            && resolver.last() != config.resolved_host
+                        {
+                            config.resolved_host = resolver.last();
+                            let _ = config.save(&mut nvmc);
+                        }
+                    }
+                }
+                let action = connectivity.handle(event, clock_ms);
+                attach = connect(
+                    &mut connectivity,
+                    action,
+                    &mut link,
+                    &config,
+                    &mut resolver,
+                    &mut system_mode,
+                    clock_ms,
+                )
+                .or(attach);
+            }
+
+            // All done. Time to sleep, having fed our watchdog should all have
+            // gone well.
+
+            if outcome != Some(Event::LinkFailed) {
+                if let Some(watchdog) = watchdog.as_mut() {
+                    watchdog.feed();
+                }
+            }
 
             rgb_pwm.next_step();
             rgb_pwm.set_duty_on_common(rgb_pwm.get_max_duty());
+
+            clock_ms += u64::from(wake_ms);
+            since_send_ms = since_send_ms.saturating_add(wake_ms);
+            since_diagnostics_ms = since_diagnostics_ms.saturating_add(wake_ms);
         }
 
         asm::wfe();
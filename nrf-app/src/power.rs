@@ -0,0 +1,81 @@
+///! Power saving is requested of the network before attaching, and what it
+///! granted may be read back while attached. eDRX is requested for the system
+///! mode that we're attaching with.
+use app::{
+    power::{
+        encode_active_time, encode_edrx_cycle, encode_periodic_tau, encode_ptw, granted_edrx,
+        granted_psm, Edrx, Psm,
+    },
+    system_mode::SystemMode,
+};
+use core::fmt::Write;
+use heapless::String;
+
+// Room for the longest of our commands, with some to spare.
+
+const COMMAND_LEN: usize = 48;
+
+/// Request PSM and eDRX, disabling either if not given, and returning whether
+/// the modem accepted the requests.
+pub fn request(mode: SystemMode, psm: Option<&Psm>, edrx: Option<&Edrx>) -> bool {
+    let mut psm_command: String<COMMAND_LEN> = String::new();
+    let psm_written = match psm {
+        Some(psm) => write!(
+            psm_command,
+            "AT+CPSMS=1,,,\"{:08b}\",\"{:08b}\"",
+            encode_periodic_tau(psm.periodic_tau_s),
+            encode_active_time(psm.active_time_s)
+        ),
+        None => write!(psm_command, "AT+CPSMS=0"),
+    };
+
+    // The access technology types of AT+CEDRXS
+    let act = match mode {
+        SystemMode::LteM => 4,
+        SystemMode::NbIot => 5,
+    };
+    let mut edrx_command: String<COMMAND_LEN> = String::new();
+    let mut ptw_command: String<COMMAND_LEN> = String::new();
+    let edrx_written = match edrx {
+        Some(edrx) => write!(
+            edrx_command,
+            "AT+CEDRXS=2,{},\"{:04b}\"",
+            act,
+            encode_edrx_cycle(mode, edrx.cycle_ms)
+        )
+        .and_then(|_| {
+            write!(
+                ptw_command,
+                "AT%XPTW={},\"{:04b}\"",
+                act,
+                encode_ptw(mode, edrx.ptw_ms)
+            )
+        }),
+        None => write!(edrx_command, "AT+CEDRXS=3"),
+    };
+
+    // Rather than send a command cut short
+    if psm_written.is_err() || edrx_written.is_err() {
+        return false;
+    }
+
+    [psm_command, edrx_command, ptw_command]
+        .iter()
+        .filter(|command| !command.is_empty())
+        .all(|command| nrfxlib::at::send_at_command(command, |_| ()).is_ok())
+}
+
+/// The PSM and eDRX values granted by the network, if any.
+pub fn granted() -> (Option<Psm>, Option<Edrx>) {
+    let mut psm = None;
+    let _ = nrfxlib::at::send_at_command("AT+CEREG=5", |_| ()).and_then(|_| {
+        nrfxlib::at::send_at_command("AT+CEREG?", |response| {
+            psm = psm.or_else(|| response.lines().find_map(granted_psm));
+        })
+    });
+    let mut edrx = None;
+    let _ = nrfxlib::at::send_at_command("AT+CEDRXRDP", |response| {
+        edrx = edrx.or_else(|| response.lines().find_map(granted_edrx));
+    });
+    (psm, edrx)
+}
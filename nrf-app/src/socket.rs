@@ -0,0 +1,131 @@
+///! A UDP socket connected to either an IPv4 or an IPv6 address. The modem's
+///! sockets are used directly so that the socket's family may match that of
+///! the address, some operators only providing IPv6 PDN contexts. Sockets may
+///! also be secured with DTLS 1.2, in which case connecting performs the
+///! handshake using the credentials held by the modem under a security tag.
+use app::host::IpAddr;
+use core::mem::size_of;
+use nrfxlib_sys as sys;
+
+pub struct UdpSocket {
+    fd: i32,
+}
+
+impl UdpSocket {
+    /// Open a socket of the address's family and connect it to the address,
+    /// securing it with the credentials of a security tag if given. Returns
+    /// `None` if any of that fails.
+    pub fn connect(addr: &IpAddr, port: u16, sec_tag: Option<u32>) -> Option<Self> {
+        let family = match addr {
+            IpAddr::V4(_) => sys::NRF_AF_INET,
+            IpAddr::V6(_) => sys::NRF_AF_INET6,
+        };
+        let protocol = match sec_tag {
+            Some(_) => sys::NRF_SPROTO_DTLS1v2,
+            None => sys::NRF_IPPROTO_UDP,
+        };
+        let fd = unsafe { sys::nrf_socket(family as _, sys::NRF_SOCK_DGRAM as _, protocol as _) };
+        if fd < 0 {
+            return None;
+        }
+        let socket = UdpSocket { fd };
+
+        if let Some(sec_tag) = sec_tag {
+            // The pre-shared key authenticates the server, so there's no
+            // certificate to verify.
+            let peer_verify: u32 = 0;
+            let secured = socket.set_secure_option(sys::NRF_SO_SEC_TAG_LIST as _, &sec_tag)
+                && socket.set_secure_option(sys::NRF_SO_SEC_PEER_VERIFY as _, &peer_verify);
+            if !secured {
+                return None;
+            }
+        }
+
+        // Ports and addresses are in network byte order.
+        let status = match addr {
+            IpAddr::V4(octets) => {
+                let mut sockaddr: sys::nrf_sockaddr_in = unsafe { core::mem::zeroed() };
+                sockaddr.sin_len = size_of::<sys::nrf_sockaddr_in>() as _;
+                sockaddr.sin_family = sys::NRF_AF_INET as _;
+                sockaddr.sin_port = port.to_be();
+                sockaddr.sin_addr.s_addr = u32::from_ne_bytes(*octets);
+                unsafe {
+                    sys::nrf_connect(
+                        fd,
+                        &sockaddr as *const _ as *const _,
+                        size_of::<sys::nrf_sockaddr_in>() as _,
+                    )
+                }
+            }
+            IpAddr::V6(octets) => {
+                let mut sockaddr: sys::nrf_sockaddr_in6 = unsafe { core::mem::zeroed() };
+                sockaddr.sin6_len = size_of::<sys::nrf_sockaddr_in6>() as _;
+                sockaddr.sin6_family = sys::NRF_AF_INET6 as _;
+                sockaddr.sin6_port = port.to_be();
+                sockaddr.sin6_addr.s6_addr = *octets;
+                unsafe {
+                    sys::nrf_connect(
+                        fd,
+                        &sockaddr as *const _ as *const _,
+                        size_of::<sys::nrf_sockaddr_in6>() as _,
+                    )
+                }
+            }
+        };
+        if status == 0 {
+            Some(socket)
+        } else {
+            None // Dropping the socket closes it
+        }
+    }
+
+    fn set_secure_option(&self, option: u32, value: &u32) -> bool {
+        let status = unsafe {
+            sys::nrf_setsockopt(
+                self.fd,
+                sys::NRF_SOL_SECURE as _,
+                option as _,
+                value as *const u32 as *const _,
+                size_of::<u32>() as _,
+            )
+        };
+        status == 0
+    }
+
+    /// Send a datagram, returning whether the modem accepted all of it.
+    pub fn write(&self, datagram: &[u8]) -> bool {
+        let sent = unsafe {
+            sys::nrf_send(
+                self.fd,
+                datagram.as_ptr() as *const _,
+                datagram.len() as _,
+                0,
+            )
+        };
+        sent == datagram.len() as _
+    }
+
+    /// Receive a datagram without waiting, returning its length if there
+    /// was one.
+    pub fn recv(&self, buf: &mut [u8]) -> Option<usize> {
+        let received = unsafe {
+            sys::nrf_recv(
+                self.fd,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as _,
+                sys::NRF_MSG_DONTWAIT as _,
+            )
+        };
+        if received >= 0 {
+            Some(received as usize)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        unsafe { sys::nrf_close(self.fd) };
+    }
+}
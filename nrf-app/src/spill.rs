@@ -0,0 +1,78 @@
+///! Uplinks evicted from our queue are spilled to the first flash page of our
+///! configuration region. Records are appended, and marked as consumed once
+///! taken back, with the page erased only once none remain pending. Should the
+///! page fill with pending records then further uplinks are dropped.
+use app::{
+    config::ConfigError,
+    queue::{find_newest_pending, Uplink, CONSUMED, CONSUMED_OFFSET, SPILL_RECORD_LEN},
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use nrf_hal_common::{nvmc::Nvmc, pac::NVMC_NS};
+
+const OFFSET: u32 = 0;
+const PAGE_SIZE: u32 = 4096;
+
+pub struct SpillLog {
+    next: u32,
+}
+
+impl SpillLog {
+    pub fn load(nvmc: &mut Nvmc<NVMC_NS>) -> Result<Self, ConfigError> {
+        let (_, next) = Self::scan(nvmc)?;
+        Ok(SpillLog { next: next as u32 })
+    }
+
+    /// A log that couldn't be loaded, and so is taken to be full. Nothing
+    /// further is spilled until it can be scanned and found to have nothing
+    /// pending.
+    pub fn unloaded() -> Self {
+        SpillLog { next: PAGE_SIZE }
+    }
+
+    fn scan(nvmc: &mut Nvmc<NVMC_NS>) -> Result<(Option<(usize, Uplink)>, usize), ConfigError> {
+        let mut page = [0u8; PAGE_SIZE as usize];
+        nvmc.try_read(OFFSET, &mut page)
+            .map_err(|_| ConfigError::Read)?;
+        Ok(find_newest_pending(&page))
+    }
+
+    fn erase(&mut self, nvmc: &mut Nvmc<NVMC_NS>) -> Result<(), ConfigError> {
+        nvmc.try_erase(OFFSET, OFFSET + PAGE_SIZE)
+            .map_err(|_| ConfigError::Erase)?;
+        self.next = 0;
+        Ok(())
+    }
+
+    pub fn store(&mut self, nvmc: &mut Nvmc<NVMC_NS>, uplink: &Uplink) -> Result<(), ConfigError> {
+        if self.next + SPILL_RECORD_LEN as u32 > PAGE_SIZE {
+            match Self::scan(nvmc)? {
+                (None, _) => self.erase(nvmc)?,
+                _ => return Err(ConfigError::Overflow),
+            }
+        }
+        nvmc.try_write(OFFSET + self.next, &uplink.to_spill_record())
+            .map_err(|_| ConfigError::Write)?;
+        self.next += SPILL_RECORD_LEN as u32;
+        Ok(())
+    }
+
+    /// Take back the most recently spilled uplink, if any. Those spilled
+    /// are taken newest first given that they return to the oldest end of
+    /// our queue.
+    pub fn take(&mut self, nvmc: &mut Nvmc<NVMC_NS>) -> Option<Uplink> {
+        if self.next == 0 {
+            return None;
+        }
+        match Self::scan(nvmc).ok()? {
+            (Some((offset, uplink)), _) => {
+                nvmc.try_write(OFFSET + (offset + CONSUMED_OFFSET) as u32, &CONSUMED)
+                    .ok()?;
+                Some(uplink)
+            }
+            (None, _) => {
+                let _ = self.erase(nvmc);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+///! The hardware watchdog resets us should we hang, be it reading our sensor or
+///! waiting on the modem. The reason for our last reset is read back when we
+///! start.
+use app::reset::ResetReason;
+use nrf_hal_common::{
+    pac::{POWER_NS, WDT_NS},
+    wdt::{self, count, handles::Hdl0, WatchdogHandle},
+};
+
+/// Read the reason for our last reset, clearing it so that the next reset's
+/// reason isn't confused with this one's.
+pub fn reset_reason() -> ResetReason {
+    let power = unsafe { &*POWER_NS::ptr() };
+    let bits = power.resetreas.read().bits();
+    power.resetreas.write(|w| unsafe { w.bits(bits) }); // Cleared by writing ones
+    ResetReason::from_resetreas(bits)
+}
+
+pub struct Watchdog(WatchdogHandle<Hdl0>);
+
+impl Watchdog {
+    /// Start the watchdog with a timeout of so many low frequency clock ticks.
+    /// Once started, it can't be stopped, so should it have survived a soft
+    /// reset then we carry on feeding it with its existing timeout. Should
+    /// that fail, as it does if it was running with other handles, there's no
+    /// watchdog that we can feed.
+    pub fn start(wdt: WDT_NS, ticks: u32) -> Option<Self> {
+        let parts = match wdt::Watchdog::try_new(wdt) {
+            Ok(mut watchdog) => {
+                watchdog.set_lfosc_ticks(ticks);
+                watchdog.run_during_sleep(true);
+                watchdog.halt_during_debug(true);
+                watchdog.activate::<count::One>()
+            }
+            Err(wdt) => wdt::Watchdog::try_recover::<count::One>(wdt).ok()?,
+        };
+        let (handle,) = parts.handles;
+        Some(Watchdog(handle))
+    }
+
+    /// The watchdog, should it have survived a soft reset, which we must then
+    /// keep feeding. Otherwise it is left stopped.
+    pub fn recover(wdt: WDT_NS) -> Option<Self> {
+        match wdt::Watchdog::try_new(wdt) {
+            Ok(_) => None,
+            Err(wdt) => wdt::Watchdog::try_recover::<count::One>(wdt)
+                .ok()
+                .map(|parts| Watchdog(parts.handles.0)),
+        }
+    }
+
+    pub fn feed(&mut self) {
+        self.0.pet();
+    }
+}
@@ -0,0 +1,178 @@
+//! Devices to provision are listed as CSV, one per line following a header
+//! that names the columns given, in any order. Fields are unquoted, and an
+//! empty field leaves the configuration as new:
+//!
+//! Column            | Description
+//! iccid             | The device's ICCID, by which its image is named
+//! nwkskey           | The network session key for ABP, as 32 hex digits
+//! appskey           | The application session key for ABP, as 32 hex digits
+//! dev_eui           | The Device EUI for OTAA, as 16 hex digits
+//! join_eui          | The Join EUI for OTAA, as 16 hex digits
+//! app_key           | The application key for OTAA, as 32 hex digits
+//! server            | The network server's hostname or IP address
+//! port              | The network server's port
+//! send_frequency_ms | How often readings are sent
+//!
+//! Keys and EUIs may be prefixed with `0x`, as the console accepts them.
+
+use std::fmt;
+
+use app::{
+    config::Config,
+    host::{parse_hostname, IpAddr},
+};
+
+#[derive(Debug, PartialEq)]
+pub enum DevicesError {
+    /// A column of the header that we don't know.
+    UnknownColumn(String),
+    /// Each device is required to have an ICCID.
+    NoIccid,
+    /// Lines number from 1, and include the header.
+    Invalid { line: usize, column: String },
+    /// Neither activated by personalisation nor over the air, or without a
+    /// network server.
+    Incomplete { line: usize },
+}
+
+impl fmt::Display for DevicesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DevicesError::UnknownColumn(column) => write!(f, "unknown column {}", column),
+            DevicesError::NoIccid => write!(f, "an iccid column is required"),
+            DevicesError::Invalid { line, column } => {
+                write!(f, "line {} has an invalid {}", line, column)
+            }
+            DevicesError::Incomplete { line } => {
+                write!(f, "line {} is not a complete configuration", line)
+            }
+        }
+    }
+}
+
+/// A device to provision, and its configuration.
+pub struct Device {
+    pub iccid: u64,
+    pub config: Config,
+}
+
+fn hex_digits(text: &str, len: usize) -> Option<&str> {
+    let digits = text.trim_start_matches("0x");
+    (digits.len() == len).then_some(digits)
+}
+
+fn key(text: &str) -> Option<u128> {
+    u128::from_str_radix(hex_digits(text, 32)?, 16).ok()
+}
+
+fn eui(text: &str) -> Option<u64> {
+    u64::from_str_radix(hex_digits(text, 16)?, 16).ok()
+}
+
+// Set the field of a column, returning None should it be invalid.
+
+fn set(config: &mut Config, column: &str, text: &str) -> Option<()> {
+    match column {
+        "iccid" => config.iccid = Some(text.parse().ok()?),
+        "nwkskey" => config.nwkskey = Some(key(text)?),
+        "appskey" => config.appskey = Some(key(text)?),
+        "dev_eui" => config.dev_eui = Some(eui(text)?),
+        "join_eui" => config.join_eui = Some(eui(text)?),
+        "app_key" => config.app_key = Some(key(text)?),
+        "server" => {
+            if let Some(addr) = IpAddr::parse(text) {
+                config.network_server_host = Some(addr);
+                config.network_server_name = None;
+            } else {
+                config.network_server_host = None;
+                config.network_server_name = Some(parse_hostname(text)?);
+            }
+        }
+        "port" => config.network_server_port = text.parse().ok()?,
+        "send_frequency_ms" => config.send_frequency_ms = text.parse().ok()?,
+        _ => return None,
+    }
+    Some(())
+}
+
+const COLUMNS: [&str; 9] = [
+    "iccid",
+    "nwkskey",
+    "appskey",
+    "dev_eui",
+    "join_eui",
+    "app_key",
+    "server",
+    "port",
+    "send_frequency_ms",
+];
+
+/// Parse the devices listed, each of which must have a complete
+/// configuration. Blank lines are passed over.
+///
+/// ```
+/// use app::host::IpAddr;
+/// use provision::devices::{parse, DevicesError};
+///
+/// let devices = parse(
+///     "iccid,nwkskey,appskey,dev_eui,join_eui,app_key,server,port,send_frequency_ms\n\
+///      8944000000000000001,0102030405060708090a0b0c0d0e0f10,1112131415161718191a1b1c1d1e1f20,,,,192.168.0.10,1700,900000\n\
+///      \n\
+///      8944000000000000002,,,0x0102030405060708,1112131415161718,2122232425262728292a2b2c2d2e2f30,lns.example.com,,\n",
+/// )
+/// .unwrap();
+/// assert_eq!(devices.len(), 2);
+/// assert_eq!(devices[0].iccid, 8944000000000000001);
+/// assert!(devices[0].config.is_abp());
+/// assert_eq!(devices[0].config.network_server_host, Some(IpAddr::V4([192, 168, 0, 10])));
+/// assert_eq!(devices[0].config.network_server_port, 1700);
+/// assert_eq!(devices[0].config.send_frequency_ms, 900_000);
+/// assert!(devices[1].config.is_otaa());
+/// assert_eq!(devices[1].config.dev_eui, Some(0x0102030405060708));
+/// assert_eq!(devices[1].config.network_server_name.as_deref(), Some("lns.example.com"));
+/// assert_eq!(devices[1].config.network_server_port, 1694);
+///
+/// assert_eq!(parse("iccid,colour\n").err(), Some(DevicesError::UnknownColumn("colour".into())));
+/// assert_eq!(parse("server\n").err(), Some(DevicesError::NoIccid));
+/// assert_eq!(
+///     parse("iccid,app_key\n1,0102\n").err(),
+///     Some(DevicesError::Invalid { line: 2, column: "app_key".into() })
+/// );
+/// assert_eq!(parse("iccid,server\n1,\n").err(), Some(DevicesError::Incomplete { line: 2 }));
+/// ```
+pub fn parse(text: &str) -> Result<Vec<Device>, DevicesError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    let header: Vec<&str> = match lines.next() {
+        Some((_, line)) => line.split(',').map(str::trim).collect(),
+        None => Vec::new(),
+    };
+    if let Some(column) = header.iter().find(|column| !COLUMNS.contains(column)) {
+        return Err(DevicesError::UnknownColumn(column.to_string()));
+    }
+    if !header.contains(&"iccid") {
+        return Err(DevicesError::NoIccid);
+    }
+
+    let mut devices = Vec::new();
+    for (line, fields) in lines {
+        let mut config = Config::new();
+        for (column, field) in header.iter().zip(fields.split(',').map(str::trim)) {
+            if !field.is_empty() && set(&mut config, column, field).is_none() {
+                return Err(DevicesError::Invalid {
+                    line,
+                    column: column.to_string(),
+                });
+            }
+        }
+        match config.iccid {
+            Some(iccid) if config.is_complete() => devices.push(Device { iccid, config }),
+            _ => return Err(DevicesError::Incomplete { line }),
+        }
+    }
+    Ok(devices)
+}
@@ -0,0 +1,160 @@
+//! Intel HEX, as flashing tools such as `nrfjprog` and `mergehex` take. Each
+//! record is a line of hex digits following a colon: its data length, a
+//! 16 bit address, its type, its data, then a checksum that brings the sum of
+//! its bytes to zero. Addresses beyond 16 bits are formed with extended
+//! address records.
+
+use std::fmt;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+
+// The most data that we place in a record.
+
+const RECORD_DATA_LEN: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum HexError {
+    /// Not a record, or of a type that we don't know. Lines number from 1.
+    Malformed {
+        line: usize,
+    },
+    Checksum {
+        line: usize,
+    },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexError::Malformed { line } => write!(f, "line {} is not a record", line),
+            HexError::Checksum { line } => write!(f, "line {} has a bad checksum", line),
+        }
+    }
+}
+
+fn record(text: &mut String, address: u16, record_type: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+
+    text.push(':');
+    for byte in bytes {
+        text.push_str(&format!("{:02X}", byte));
+    }
+    text.push('\n');
+}
+
+/// Encode bytes to be flashed at the address given.
+///
+/// ```
+/// use provision::ihex::encode;
+///
+/// assert_eq!(
+///     encode(0x000f_fffe, &[0x01, 0x02, 0x03]),
+///     ":02000004000FEB\n\
+///      :02FFFE000102FE\n\
+///      :020000040010EA\n\
+///      :0100000003FC\n\
+///      :00000001FF\n"
+/// );
+/// ```
+pub fn encode(address: u32, bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut upper = None;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let start = address + offset as u32;
+        if upper != Some(start >> 16) {
+            record(
+                &mut text,
+                0,
+                EXTENDED_LINEAR_ADDRESS,
+                &((start >> 16) as u16).to_be_bytes(),
+            );
+            upper = Some(start >> 16);
+        }
+        // Records don't cross a 64K boundary
+        let len = (bytes.len() - offset)
+            .min(RECORD_DATA_LEN)
+            .min(0x1_0000 - (start & 0xffff) as usize);
+        record(&mut text, start as u16, DATA, &bytes[offset..offset + len]);
+        offset += len;
+    }
+    record(&mut text, 0, END_OF_FILE, &[]);
+    text
+}
+
+fn parse_record(line: &str) -> Option<Vec<u8>> {
+    let digits = line.strip_prefix(':')?;
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return None;
+    }
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    (bytes.len() >= 5 && bytes.len() == 5 + usize::from(bytes[0])).then_some(bytes)
+}
+
+/// Decode the data of the region starting at the address given, ignoring
+/// any data outside of it, so that the region may be found amongst others
+/// that have been merged. The region retains what it held wherever there is
+/// no data.
+///
+/// ```
+/// use provision::ihex::{decode, encode, HexError};
+///
+/// let mut region = [0xff; 4];
+/// let text = encode(0x000f_fffe, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+/// decode(&text, 0x0010_0000, &mut region).unwrap();
+/// assert_eq!(region, [0x03, 0x04, 0x05, 0x06]);
+///
+/// assert_eq!(decode(":0100000003FD\n", 0, &mut region), Err(HexError::Checksum { line: 1 }));
+/// assert_eq!(decode("\n:01000000\n", 0, &mut region), Err(HexError::Malformed { line: 2 }));
+/// ```
+pub fn decode(text: &str, address: u32, region: &mut [u8]) -> Result<(), HexError> {
+    let region_start = u64::from(address);
+    let region_end = region_start + region.len() as u64;
+    let mut base = 0u64;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bytes = parse_record(line).ok_or(HexError::Malformed { line: i + 1 })?;
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(HexError::Checksum { line: i + 1 });
+        }
+        let offset = u64::from(u16::from_be_bytes([bytes[1], bytes[2]]));
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA => {
+                for (j, byte) in data.iter().enumerate() {
+                    let at = base + offset + j as u64;
+                    if (region_start..region_end).contains(&at) {
+                        region[(at - region_start) as usize] = *byte;
+                    }
+                }
+            }
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let shift = if bytes[3] == EXTENDED_LINEAR_ADDRESS {
+                    16
+                } else {
+                    4
+                };
+                base = u64::from(u16::from_be_bytes([data[0], data[1]])) << shift;
+            }
+            // Start addresses, which don't concern us
+            0x03 | 0x05 => (),
+            _ => return Err(HexError::Malformed { line: i + 1 }),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Provisions devices in bulk during their manufacture, by producing an
+//! image of each device's configuration to be flashed along with the SPM and
+//! our firmware. Images are of our whole configuration region, as laid out
+//! by `memory.x`, holding a configuration saved just as the device saves
+//! it. The region's other pages are erased, so that frame counters and
+//! spilled uplinks don't outlive any earlier provisioning.
+
+use std::fmt;
+
+use app::{
+    config::{Config, ConfigError},
+    flash::RamFlash,
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+pub mod devices;
+pub mod ihex;
+
+use ihex::HexError;
+
+/// The address of our configuration region, being `_config` of `memory.x`.
+pub const CONFIG_ADDRESS: u32 = 0x000f_b000;
+
+/// The length of our configuration region: spilled uplinks, frame counters
+/// in two pages, then two config slots.
+pub const CONFIG_LEN: usize = 20 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ImageError {
+    Hex(HexError),
+    Config(ConfigError),
+    /// No configuration, or one that the device can't run with.
+    Incomplete,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Hex(e) => write!(f, "{}", e),
+            ImageError::Config(e) => write!(f, "configuration {}", e),
+            ImageError::Incomplete => write!(f, "configuration is incomplete"),
+        }
+    }
+}
+
+/// Produce an image of our configuration region holding the configuration,
+/// as Intel HEX.
+///
+/// ```
+/// use provision::{decode, image, ImageError, CONFIG_ADDRESS};
+/// use provision::devices::parse;
+///
+/// let devices = parse(
+///     "iccid,nwkskey,appskey,server\n\
+///      8944000000000000001,0102030405060708090a0b0c0d0e0f10,1112131415161718191a1b1c1d1e1f20,lns.example.com\n",
+/// )
+/// .unwrap();
+/// let text = image(&devices[0].config).unwrap();
+/// assert!(text.starts_with(":02000004000FEB\n"));
+///
+/// let config = decode(&text).unwrap();
+/// assert_eq!(config.iccid, Some(8944000000000000001));
+/// assert_eq!(config.nwkskey, Some(0x0102030405060708090a0b0c0d0e0f10));
+/// assert_eq!(config.network_server_name.as_deref(), Some("lns.example.com"));
+///
+/// // As found amongst the SPM and our firmware
+/// let merged = format!(":020000040000FA\n:0400000001020304F2\n{}", text);
+/// assert!(decode(&merged).is_ok());
+///
+/// // Without a configuration
+/// assert_eq!(decode(":00000001FF\n").err(), Some(ImageError::Incomplete));
+/// ```
+pub fn image(config: &Config) -> Result<String, ImageError> {
+    let mut flash = RamFlash::<CONFIG_LEN>::new();
+    config.save(&mut flash).map_err(ImageError::Config)?;
+    let mut region = vec![0; CONFIG_LEN];
+    flash
+        .try_read(0, &mut region)
+        .map_err(|_| ImageError::Config(ConfigError::Read))?;
+    Ok(ihex::encode(CONFIG_ADDRESS, &region))
+}
+
+/// Decode the configuration of an image, as the device would load it. The
+/// image may have been merged with others.
+pub fn decode(text: &str) -> Result<Config, ImageError> {
+    let mut region = vec![0xff; CONFIG_LEN];
+    ihex::decode(text, CONFIG_ADDRESS, &mut region).map_err(ImageError::Hex)?;
+    let mut flash = RamFlash::<CONFIG_LEN>::new();
+    flash
+        .try_write(0, &region)
+        .map_err(|_| ImageError::Config(ConfigError::Write))?;
+    let config = Config::load(&mut flash).map_err(ImageError::Config)?;
+    if config.is_complete() {
+        Ok(config)
+    } else {
+        Err(ImageError::Incomplete)
+    }
+}
@@ -0,0 +1,84 @@
+//! Produces an image of each device's configuration, or verifies an image:
+//!
+//! ```text
+//! provision images DEVICES_CSV OUT_DIR
+//! provision verify IMAGE_HEX
+//! ```
+//!
+//! Images may then be merged with the SPM and our firmware, and flashed in
+//! one step e.g.:
+//!
+//! ```text
+//! mergehex -m spm.hex thingy91-lorawan-nbiot.hex OUT_DIR/ICCID.hex -o device.hex
+//! nrfjprog --program device.hex --sectorerase --verify --reset
+//! ```
+
+use std::{env, fs, path::Path, process};
+
+use app::config::{Config, MAX_ENCODED_LEN};
+use provision::{decode, devices, image};
+
+const USAGE: &str = "usage: provision images DEVICES_CSV OUT_DIR | provision verify IMAGE_HEX";
+
+fn encoded(config: &Config) -> Vec<u8> {
+    let mut buf = [0; MAX_ENCODED_LEN];
+    config
+        .encode(&mut buf)
+        .map(|bytes| bytes.to_vec())
+        .unwrap_or_default()
+}
+
+fn images(devices_csv: &str, out_dir: &str) -> Result<(), String> {
+    let text = fs::read_to_string(devices_csv).map_err(|e| format!("{}: {}", devices_csv, e))?;
+    let devices = devices::parse(&text).map_err(|e| format!("{}: {}", devices_csv, e))?;
+    fs::create_dir_all(out_dir).map_err(|e| format!("{}: {}", out_dir, e))?;
+    for device in devices {
+        let path = Path::new(out_dir).join(format!("{}.hex", device.iccid));
+        let text = image(&device.config).map_err(|e| format!("{}: {}", device.iccid, e))?;
+        match decode(&text) {
+            Ok(config) if encoded(&config) == encoded(&device.config) => (),
+            _ => return Err(format!("{}: image doesn't decode as given", device.iccid)),
+        }
+        fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn verify(image_hex: &str) -> Result<(), String> {
+    let text = fs::read_to_string(image_hex).map_err(|e| format!("{}: {}", image_hex, e))?;
+    let config = decode(&text).map_err(|e| format!("{}: {}", image_hex, e))?;
+    if let Some(iccid) = config.iccid {
+        println!("ICCID:\t\t\t {}", iccid);
+    }
+    if config.is_otaa() {
+        println!("ACTIVATION:\t\t OTAA");
+    } else {
+        println!("ACTIVATION:\t\t ABP");
+    }
+    if let Some(dev_eui) = config.dev_eui {
+        println!("DEV_EUI:\t\t 0x{:016X}", dev_eui);
+    }
+    if let Some(network_server_host) = config.network_server_host {
+        println!("NETWORK_SERVER_HOST:\t {}", network_server_host);
+    } else if let Some(network_server_name) = &config.network_server_name {
+        println!("NETWORK_SERVER_HOST:\t {}", network_server_name);
+    }
+    println!("NETWORK_SERVER_PORT:\t {}", config.network_server_port);
+    println!("SEND_FREQUENCY_MS:\t {}", config.send_frequency_ms);
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["images", devices_csv, out_dir] => images(devices_csv, out_dir),
+        ["verify", image_hex] => verify(image_hex),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}